@@ -5,13 +5,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
     // Attempt to create a mapping or open if it already exists
     println!("Getting the shared memory mapping");
-    let shmem = match ShmemConf::new().size(4096).flink("event_mapping").create() {
-        Ok(m) => m,
-        Err(ShmemError::LinkExists) => ShmemConf::new().flink("event_mapping").open()?,
-        Err(e) => return Err(Box::new(e)),
-    };
+    let (shmem, created) = ShmemConf::new()
+        .size(4096)
+        .flink("event_mapping")
+        .open_or_create()?;
 
-    if shmem.is_owner() {
+    if created {
         //Create an event in the shared memory
         println!("Creating event in shared memory");
         let (evt, used_bytes) = unsafe { Event::new(shmem.as_ptr(), true)? };
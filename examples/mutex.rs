@@ -1,4 +1,3 @@
-use std::sync::atomic::{AtomicU8, Ordering};
 use std::thread;
 
 use clap::Parser;
@@ -44,48 +43,39 @@ fn main() {
 
 fn increment_value(shmem_flink: &str, thread_num: usize) {
     // Create or open the shared memory mapping
-    let shmem = match ShmemConf::new().size(4096).flink(shmem_flink).create() {
-        Ok(m) => m,
-        Err(ShmemError::LinkExists) => ShmemConf::new().flink(shmem_flink).open().unwrap(),
+    let shmem = match ShmemConf::new()
+        .size(4096)
+        .flink(shmem_flink)
+        .init(|shmem| {
+            // Initialize the mutex, openers wait until this closure returns
+            let raw_ptr = shmem.as_ptr();
+            let (lock, _bytes_used) = unsafe {
+                Mutex::new(
+                    raw_ptr,                                    // Base address of Mutex
+                    raw_ptr.add(Mutex::size_of(Some(raw_ptr))), // Address of data protected by mutex
+                )
+                .unwrap()
+            };
+            // Keep the mutex alive for the lifetime of the process, everyone loads it with from_existing()
+            std::mem::forget(lock);
+        })
+        .open_or_create()
+    {
+        Ok((m, _created)) => m,
         Err(e) => {
             eprintln!("Unable to create or open shmem flink {shmem_flink} : {e}");
             return;
         }
     };
 
-    let mut raw_ptr = shmem.as_ptr();
-    let is_init: &mut AtomicU8;
-
-    unsafe {
-        is_init = &mut *(raw_ptr as *mut u8 as *mut AtomicU8);
-        raw_ptr = raw_ptr.add(8);
-    };
-
-    // Initialize or wait for initialized mutex
-    let mutex = if shmem.is_owner() {
-        is_init.store(0, Ordering::Relaxed);
-        // Initialize the mutex
-        let (lock, _bytes_used) = unsafe {
-            Mutex::new(
-                raw_ptr,                                    // Base address of Mutex
-                raw_ptr.add(Mutex::size_of(Some(raw_ptr))), // Address of data protected by mutex
-            )
-            .unwrap()
-        };
-        is_init.store(1, Ordering::Relaxed);
-        lock
-    } else {
-        // wait until mutex is initialized
-        while is_init.load(Ordering::Relaxed) != 1 {}
-        // Load existing mutex
-        let (lock, _bytes_used) = unsafe {
-            Mutex::from_existing(
-                raw_ptr,                                    // Base address of Mutex
-                raw_ptr.add(Mutex::size_of(Some(raw_ptr))), // Address of data  protected by mutex
-            )
-            .unwrap()
-        };
-        lock
+    // Load the initialized mutex
+    let raw_ptr = shmem.as_ptr();
+    let (mutex, _bytes_used) = unsafe {
+        Mutex::from_existing(
+            raw_ptr,                                    // Base address of Mutex
+            raw_ptr.add(Mutex::size_of(Some(raw_ptr))), // Address of data  protected by mutex
+        )
+        .unwrap()
     };
 
     // Loop until mutex data reaches 10
@@ -0,0 +1,128 @@
+//! Traits implemented by the objects that back shared memory mappings
+
+#[cfg(unix)]
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+#[cfg(unix)]
+use crate::Protection;
+use crate::ShmemError;
+
+/// Creates, opens and removes the objects that back shared memory mappings
+///
+/// The backend of a mapping is selected with `ShmemConf::backend()`. When none is set, the OS shared memory
+/// facilities are used (POSIX shared memory on unix, file mappings on Windows) along with the OS specific options
+/// of `ShmemConf` such as `memfd()`, `file_backed()` or `sysv()`.
+pub trait ShmemBackend: Send + Sync {
+    /// Creates a new object identified by `os_id` and maps `size` bytes of it
+    ///
+    /// Must fail with `ShmemError::MappingIdExists` when an object with the same id already exists
+    fn create(&self, os_id: &str, size: usize) -> Result<Box<dyn ShmemMapping>, ShmemError>;
+
+    /// Maps an existing object identified by `os_id`
+    ///
+    /// `size` is the size requested by the caller of `ShmemConf::open()`, 0 when unknown.
+    /// The object must be mapped without write access when `read_only` is set
+    fn open(
+        &self,
+        os_id: &str,
+        size: usize,
+        read_only: bool,
+    ) -> Result<Box<dyn ShmemMapping>, ShmemError>;
+
+    /// Maps `size` bytes of an existing object identified by `os_id`, starting at `offset`
+    ///
+    /// A `size` of 0 maps up to the end of the object. Must fail with `ShmemError::MapTooSmall` when the object
+    /// does not cover the window. Backends that cannot map part of an object do not need to implement it
+    fn open_window(
+        &self,
+        _os_id: &str,
+        _offset: u64,
+        _size: usize,
+        _read_only: bool,
+    ) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        Err(ShmemError::Unsupported("Opening part of a mapping"))
+    }
+
+    /// Removes the object behind `mapping`
+    ///
+    /// This is called when the owner of a mapping drops it. Processes that already mapped the object must be able
+    /// to keep using it while new calls to `open()` fail. Defaults to `ShmemMapping::unlink()`
+    fn unlink(&self, mapping: &dyn ShmemMapping) -> Result<(), ShmemError> {
+        mapping.unlink()
+    }
+}
+
+/// An object mapped into our address space by a `ShmemBackend`
+///
+/// Dropping the mapping must not remove the object, see `ShmemBackend::unlink()`.
+///
+/// # Safety
+///
+/// `Shmem` hands out slices and atomics built from `as_ptr()` so implementations must guarantee that :
+/// - `as_ptr()` points to `len()` readable bytes, also writable unless the mapping was opened read-only
+/// - these bytes stay valid until the mapping is dropped or one of the methods taking `&mut self` succeeds
+/// - `as_ptr()` is aligned to at least 8 bytes as the managed header stores atomics there
+#[allow(clippy::len_without_is_empty)]
+pub unsafe trait ShmemMapping {
+    /// Returns the id of the object
+    fn os_id(&self) -> &str;
+
+    /// Returns the size of the mapping in bytes
+    fn len(&self) -> usize;
+
+    /// Returns a pointer to the first byte of the mapping
+    fn as_ptr(&self) -> *mut u8;
+
+    /// Returns the current size of the object, which differs from `len()` when another process resized it
+    fn object_size(&self) -> Result<usize, ShmemError> {
+        Ok(self.len())
+    }
+
+    /// Changes the size of the object and maps all of it
+    fn resize(&mut self, _new_size: usize) -> Result<(), ShmemError> {
+        Err(ShmemError::Unsupported("Resizing mappings"))
+    }
+
+    /// Maps the first `new_size` bytes of the object, the mapping might move in our address space
+    fn remap(&mut self, _new_size: usize) -> Result<(), ShmemError> {
+        Err(ShmemError::Unsupported("Resizing mappings"))
+    }
+
+    /// Moves the mapping to `addr` in our address space without replacing anything that is mapped there
+    ///
+    /// Must fail with `ShmemError::AddressUnavailable` when the range is in use. The mapping must remain usable,
+    /// possibly at another address, when this fails
+    fn remap_at(&mut self, _addr: usize) -> Result<(), ShmemError> {
+        Err(ShmemError::Unsupported("Mapping at a fixed address"))
+    }
+
+    /// Removes the object, see `ShmemBackend::unlink()`
+    ///
+    /// Only needs to be implemented by mappings whose backend does not implement `ShmemBackend::unlink()`
+    fn unlink(&self) -> Result<(), ShmemError> {
+        Err(ShmemError::Unsupported("Removing mappings"))
+    }
+
+    /// Maps the same range of the object again as a copy-on-write view
+    ///
+    /// Writes to the view must not reach the object nor other mappings of it
+    fn private_view(&self) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        Err(ShmemError::Unsupported("Private views"))
+    }
+
+    /// Changes the access allowed to the pages covering `range` of the mapping, `range.start` is page aligned
+    ///
+    /// Only backends that map whole pages of the object can implement it, see `Shmem::protect()`
+    #[cfg(unix)]
+    fn protect(&self, _range: Range<usize>, _protection: Protection) -> Result<(), ShmemError> {
+        Err(ShmemError::Unsupported("Protecting mappings"))
+    }
+
+    /// Returns the file descriptor of the object if it has one
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
@@ -1,18 +1,151 @@
+use std::io;
+
+use crate::os_impl;
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ShmemError {
     MapSizeZero,
     NoLinkOrOsId,
     FlinkInvalidOsId,
-    LinkCreateFailed(std::io::Error),
-    LinkWriteFailed(std::io::Error),
+    LinkCreateFailed(io::Error),
+    LinkWriteFailed(io::Error),
     LinkExists,
-    LinkOpenFailed(std::io::Error),
-    LinkReadFailed(std::io::Error),
+    LinkOpenFailed(io::Error),
+    LinkReadFailed(io::Error),
     LinkDoesNotExist,
     MappingIdExists,
-    MapCreateFailed(u32),
-    MapOpenFailed(u32),
-    UnknownOsError(u32),
+    MapCreateFailed {
+        syscall: &'static str,
+        os_id: String,
+        source: io::Error,
+    },
+    MapOpenFailed {
+        syscall: &'static str,
+        os_id: String,
+        source: io::Error,
+    },
+    OsError {
+        syscall: &'static str,
+        os_id: String,
+        source: io::Error,
+    },
+    MapTooSmall {
+        required: usize,
+        actual: usize,
+    },
+    InitTimeout,
+    /// The creator panicked in its init closure, or died while running it.
+    /// Detecting that the creator died is only supported on Linux, openers time out elsewhere
+    InitAbandoned,
+    HeaderInvalid,
+    HeaderVersionMismatch {
+        expected: u32,
+        found: u32,
+    },
+    SchemaMismatch {
+        expected: u64,
+        found: u64,
+    },
+    Unsupported(&'static str),
+    HugePagesUnavailable(io::Error),
+    RangeOutOfBounds {
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+    MemoryLockLimit {
+        required: usize,
+        limit: u64,
+    },
+    AddressUnavailable {
+        addr: usize,
+    },
+    UnalignedOffset {
+        offset: u64,
+        align: usize,
+    },
+    InsufficientSpace {
+        required: usize,
+        available: u64,
+    },
+    PageFault {
+        offset: usize,
+    },
+}
+
+/// Broad categories of errors returned by this crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ShmemErrorKind {
+    /// The mapping or its link does not exist
+    NotFound,
+    /// The mapping or its link already exists
+    AlreadyExists,
+    /// The process lacks the permissions to access the mapping
+    PermissionDenied,
+    /// The filesystem backing the mapping is full
+    NoSpace,
+    /// A resource limit was reached (open files, memory, ...)
+    ResourceLimit,
+    /// The parameters used to create or open the mapping are invalid
+    InvalidInput,
+    /// The mapping does not contain what was expected
+    InvalidData,
+    /// Waiting on the mapping timed out
+    TimedOut,
+    /// The operation is not supported by this kind of mapping
+    Unsupported,
+    /// Any other error
+    Other,
+}
+
+impl ShmemError {
+    /// Classifies the error into a broad category
+    pub fn kind(&self) -> ShmemErrorKind {
+        match self {
+            ShmemError::MapSizeZero
+            | ShmemError::NoLinkOrOsId
+            | ShmemError::RangeOutOfBounds { .. }
+            | ShmemError::UnalignedOffset { .. } => ShmemErrorKind::InvalidInput,
+            ShmemError::LinkExists
+            | ShmemError::MappingIdExists
+            | ShmemError::AddressUnavailable { .. } => ShmemErrorKind::AlreadyExists,
+            ShmemError::LinkDoesNotExist => ShmemErrorKind::NotFound,
+            ShmemError::InsufficientSpace { .. } => ShmemErrorKind::NoSpace,
+            ShmemError::LinkCreateFailed(err)
+            | ShmemError::LinkWriteFailed(err)
+            | ShmemError::LinkOpenFailed(err)
+            | ShmemError::LinkReadFailed(err)
+            | ShmemError::MapCreateFailed { source: err, .. }
+            | ShmemError::MapOpenFailed { source: err, .. }
+            | ShmemError::OsError { source: err, .. } => ShmemErrorKind::from_io(err),
+            ShmemError::HugePagesUnavailable(_) | ShmemError::MemoryLockLimit { .. } => {
+                ShmemErrorKind::ResourceLimit
+            }
+            ShmemError::FlinkInvalidOsId
+            | ShmemError::MapTooSmall { .. }
+            | ShmemError::HeaderInvalid
+            | ShmemError::HeaderVersionMismatch { .. }
+            | ShmemError::SchemaMismatch { .. } => ShmemErrorKind::InvalidData,
+            ShmemError::InitTimeout => ShmemErrorKind::TimedOut,
+            ShmemError::InitAbandoned | ShmemError::PageFault { .. } => ShmemErrorKind::Other,
+            ShmemError::Unsupported(_) => ShmemErrorKind::Unsupported,
+        }
+    }
+}
+
+impl ShmemErrorKind {
+    fn from_io(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => ShmemErrorKind::NotFound,
+            io::ErrorKind::AlreadyExists => ShmemErrorKind::AlreadyExists,
+            io::ErrorKind::PermissionDenied => ShmemErrorKind::PermissionDenied,
+            io::ErrorKind::InvalidInput => ShmemErrorKind::InvalidInput,
+            io::ErrorKind::TimedOut => ShmemErrorKind::TimedOut,
+            _ => os_impl::error_kind(err),
+        }
+    }
 }
 
 impl std::fmt::Display for ShmemError {
@@ -28,9 +161,23 @@ impl std::fmt::Display for ShmemError {
             ShmemError::LinkReadFailed(err) => write!(f, "Reading the link file failed, {err}"),
             ShmemError::LinkDoesNotExist => f.write_str("Requested link file does not exist"),
             ShmemError::MappingIdExists => f.write_str("Shared memory OS specific ID already exists"),
-            ShmemError::MapCreateFailed(err) => write!(f, "Creating the shared memory failed, os error {err}"),
-            ShmemError::MapOpenFailed(err) => write!(f, "Opening the shared memory failed, os error {err}"),
-            ShmemError::UnknownOsError(err) => write!(f, "An unexpected OS error occurred, os error {err}"),
+            ShmemError::MapCreateFailed { syscall, os_id, source } => write!(f, "Creating the shared memory '{os_id}' failed in {syscall}(), {source}"),
+            ShmemError::MapOpenFailed { syscall, os_id, source } => write!(f, "Opening the shared memory '{os_id}' failed in {syscall}(), {source}"),
+            ShmemError::OsError { syscall, os_id, source } => write!(f, "An unexpected OS error occurred in {syscall}() for the shared memory '{os_id}', {source}"),
+            ShmemError::MapTooSmall { required, actual } => write!(f, "The shared memory mapping is {actual} bytes but at least {required} bytes are required"),
+            ShmemError::InitTimeout => f.write_str("Timed out waiting for the shared memory to be initialized"),
+            ShmemError::InitAbandoned => f.write_str("The creator of the shared memory did not complete its initialization"),
+            ShmemError::HeaderInvalid => f.write_str("The shared memory does not contain a valid header"),
+            ShmemError::HeaderVersionMismatch { expected, found } => write!(f, "The shared memory header is version {found} but version {expected} was expected"),
+            ShmemError::SchemaMismatch { expected, found } => write!(f, "The shared memory schema is {found:#X} but {expected:#X} was expected"),
+            ShmemError::Unsupported(what) => write!(f, "{what} is not supported for this shared memory"),
+            ShmemError::HugePagesUnavailable(err) => write!(f, "Not enough huge pages are available for the shared memory, {err}"),
+            ShmemError::RangeOutOfBounds { start, end, len } => write!(f, "The range {start}..{end} is out of bounds of the shared memory of {len} bytes"),
+            ShmemError::UnalignedOffset { offset, align } => write!(f, "The offset {offset:#x} is not a multiple of the page size ({align} bytes)"),
+            ShmemError::AddressUnavailable { addr } => write!(f, "The address {addr:#x} is already in use, the shared memory cannot be mapped there"),
+            ShmemError::MemoryLockLimit { required, limit } => write!(f, "Locking {required} bytes of shared memory exceeds the RLIMIT_MEMLOCK of {limit} bytes, raise it or grant CAP_IPC_LOCK"),
+            ShmemError::PageFault { offset } => write!(f, "Accessing the shared memory at offset {offset} failed, the object was truncated or its filesystem is full"),
+            ShmemError::InsufficientSpace { required, available } => write!(f, "Allocating {required} bytes of shared memory failed, only {available} bytes are free on its filesystem"),
         }
     }
 }
@@ -42,6 +189,10 @@ impl std::error::Error for ShmemError {
             ShmemError::LinkWriteFailed(err) => Some(err),
             ShmemError::LinkOpenFailed(err) => Some(err),
             ShmemError::LinkReadFailed(err) => Some(err),
+            ShmemError::MapCreateFailed { source, .. } => Some(source),
+            ShmemError::MapOpenFailed { source, .. } => Some(source),
+            ShmemError::OsError { source, .. } => Some(source),
+            ShmemError::HugePagesUnavailable(err) => Some(err),
             _ => None,
         }
     }
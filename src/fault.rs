@@ -0,0 +1,244 @@
+//! Backend wrapper that injects failures to exercise the error paths of mappings
+
+use std::io;
+#[cfg(unix)]
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+
+use crate::log::*;
+#[cfg(unix)]
+use crate::Protection;
+use crate::{ShmemBackend, ShmemError, ShmemMapping};
+
+struct Fault {
+    point: &'static str,
+    skip: usize,
+    errno: i32,
+}
+
+/// A scripted list of failures to inject, see `FaultBackend`
+///
+/// Failures are identified by the point where they happen :
+/// - `create`, `open` and `unlink` fail the calls made to the wrapped backend
+/// - `object_size`, `resize`, `remap`, `remap_at` and `private_view` fail the calls made to the mappings it returned
+///
+/// When the plan is set with `ShmemConf::fault_plan()`, these points are also available :
+/// - `flink_create`, `flink_write`, `flink_open` and `flink_read` fail the I/O on file links
+/// - on unix, the name of a syscall made by the OS backend to create, open or resize a mapping fails it (`shm_open`,
+///   `memfd_create`, `open`, `fchown`, `fchmod`, `ftruncate`, `fstat`, `mmap`, `mbind`, `posix_fallocate`,
+///   `mlock`, `shmget`, `shmctl`, `shmat`)
+///
+/// Clones share the same script, which lets tests check what was consumed with `is_done()`.
+#[derive(Clone, Default)]
+pub struct FaultPlan {
+    faults: Arc<Mutex<Vec<Fault>>>,
+}
+
+impl FaultPlan {
+    /// Creates an empty plan
+    pub fn new() -> Self {
+        FaultPlan::default()
+    }
+
+    /// Fails the next call at `point` with the OS error `errno`
+    pub fn fail(self, point: &'static str, errno: i32) -> Self {
+        self.fail_after(point, 0, errno)
+    }
+
+    /// Lets `skip` calls at `point` succeed and fails the following one with the OS error `errno`
+    ///
+    /// Faults planned for the same point are injected in the order they were added
+    pub fn fail_after(self, point: &'static str, skip: usize, errno: i32) -> Self {
+        self.faults
+            .lock()
+            .unwrap()
+            .push(Fault { point, skip, errno });
+        self
+    }
+
+    /// Returns whether every planned fault was injected
+    pub fn is_done(&self) -> bool {
+        self.faults.lock().unwrap().is_empty()
+    }
+
+    /// Consumes the fault planned for this call at `point`, if any
+    pub(crate) fn take(&self, point: &str) -> Option<io::Error> {
+        let mut faults = self.faults.lock().unwrap();
+        let idx = faults.iter().position(|f| f.point == point)?;
+        if faults[idx].skip > 0 {
+            faults[idx].skip -= 1;
+            return None;
+        }
+        let fault = faults.remove(idx);
+        debug!("Injecting error {} at {}", fault.errno, point);
+        Some(io::Error::from_raw_os_error(fault.errno))
+    }
+}
+
+/// Backend that wraps another one and makes it fail according to a `FaultPlan`
+///
+/// This is meant for testing how applications handle failures such as running out of file descriptors or
+/// space. The wrapped backend is never called for the planned failures so nothing reaches the OS. It is only
+/// available with the `fault-injection` feature.
+/// Use `ShmemConf::fault_plan()` to wrap the backend of a config and fail the syscalls of the OS backend as well.
+pub struct FaultBackend {
+    inner: Arc<dyn ShmemBackend>,
+    plan: FaultPlan,
+}
+
+impl FaultBackend {
+    /// Wraps `inner` so it fails according to `plan`
+    pub fn new<B: ShmemBackend + 'static>(inner: B, plan: FaultPlan) -> Self {
+        Self::wrap(Arc::new(inner), plan)
+    }
+
+    pub(crate) fn wrap(inner: Arc<dyn ShmemBackend>, plan: FaultPlan) -> Self {
+        Self { inner, plan }
+    }
+
+    fn wrap_mapping(&self, inner: Box<dyn ShmemMapping>) -> Box<dyn ShmemMapping> {
+        Box::new(FaultMapping {
+            inner,
+            plan: self.plan.clone(),
+        })
+    }
+}
+
+impl ShmemBackend for FaultBackend {
+    fn create(&self, os_id: &str, size: usize) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        if let Some(source) = self.plan.take("create") {
+            return Err(ShmemError::MapCreateFailed {
+                syscall: "create",
+                os_id: os_id.to_string(),
+                source,
+            });
+        }
+        let inner = self.inner.create(os_id, size)?;
+        Ok(self.wrap_mapping(inner))
+    }
+
+    fn open(
+        &self,
+        os_id: &str,
+        size: usize,
+        read_only: bool,
+    ) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        if let Some(source) = self.plan.take("open") {
+            return Err(ShmemError::MapOpenFailed {
+                syscall: "open",
+                os_id: os_id.to_string(),
+                source,
+            });
+        }
+        let inner = self.inner.open(os_id, size, read_only)?;
+        Ok(self.wrap_mapping(inner))
+    }
+
+    fn open_window(
+        &self,
+        os_id: &str,
+        offset: u64,
+        size: usize,
+        read_only: bool,
+    ) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        if let Some(source) = self.plan.take("open") {
+            return Err(ShmemError::MapOpenFailed {
+                syscall: "open",
+                os_id: os_id.to_string(),
+                source,
+            });
+        }
+        let inner = self.inner.open_window(os_id, offset, size, read_only)?;
+        Ok(self.wrap_mapping(inner))
+    }
+
+    fn unlink(&self, mapping: &dyn ShmemMapping) -> Result<(), ShmemError> {
+        if let Some(source) = self.plan.take("unlink") {
+            return Err(ShmemError::OsError {
+                syscall: "unlink",
+                os_id: mapping.os_id().to_string(),
+                source,
+            });
+        }
+        self.inner.unlink(mapping)
+    }
+}
+
+/// Mapping returned by a `FaultBackend`, it fails according to the same `FaultPlan`
+struct FaultMapping {
+    inner: Box<dyn ShmemMapping>,
+    plan: FaultPlan,
+}
+
+impl FaultMapping {
+    /// Fails with the error planned for `point`, if any
+    fn check(&self, point: &'static str) -> Result<(), ShmemError> {
+        match self.plan.take(point) {
+            Some(source) => Err(ShmemError::OsError {
+                syscall: point,
+                os_id: self.inner.os_id().to_string(),
+                source,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+// The memory is the one of the wrapped mapping
+unsafe impl ShmemMapping for FaultMapping {
+    fn os_id(&self) -> &str {
+        self.inner.os_id()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.inner.as_ptr()
+    }
+
+    fn object_size(&self) -> Result<usize, ShmemError> {
+        self.check("object_size")?;
+        self.inner.object_size()
+    }
+
+    fn resize(&mut self, new_size: usize) -> Result<(), ShmemError> {
+        self.check("resize")?;
+        self.inner.resize(new_size)
+    }
+
+    fn remap(&mut self, new_size: usize) -> Result<(), ShmemError> {
+        self.check("remap")?;
+        self.inner.remap(new_size)
+    }
+
+    fn remap_at(&mut self, addr: usize) -> Result<(), ShmemError> {
+        self.check("remap_at")?;
+        self.inner.remap_at(addr)
+    }
+
+    fn unlink(&self) -> Result<(), ShmemError> {
+        self.inner.unlink()
+    }
+
+    fn private_view(&self) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        self.check("private_view")?;
+        Ok(Box::new(FaultMapping {
+            inner: self.inner.private_view()?,
+            plan: self.plan.clone(),
+        }))
+    }
+
+    #[cfg(unix)]
+    fn protect(&self, range: Range<usize>, protection: Protection) -> Result<(), ShmemError> {
+        self.inner.protect(range, protection)
+    }
+
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        self.inner.as_raw_fd()
+    }
+}
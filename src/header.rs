@@ -0,0 +1,209 @@
+//! Header reserved at the start of mappings managed by this crate
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::log::*;
+use crate::{os_impl, ShmemError};
+
+/// Space reserved at the start of the mapping, this keeps the user data nicely aligned
+pub const HEADER_SIZE: usize = 128;
+
+/// Identifies mappings that contain a header, spells `SHMEM_RS` in memory
+const HEADER_MAGIC: u64 = u64::from_le_bytes(*b"SHMEM_RS");
+/// Bumped whenever the layout of the header changes
+const HEADER_VERSION: u32 = 1;
+
+/// Default amount of time `open()` waits for the creator to initialize the mapping
+pub const DEFAULT_INIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const STATE_UNINIT: u32 = 0;
+const STATE_INITIALIZING: u32 = 1;
+const STATE_READY: u32 = 2;
+const STATE_POISONED: u32 = 3;
+
+#[repr(C)]
+pub struct Header {
+    magic: AtomicU64,
+    version: AtomicU32,
+    state: AtomicU32,
+    /// Incremented every time the mapping is resized
+    generation: AtomicU32,
+    /// User provided identifier for the layout of the data
+    schema: AtomicU64,
+    /// Size of the user data that follows the header
+    size: AtomicU64,
+    /// Address of the mapping in the creator, 0 when it is not shared with openers
+    base_address: AtomicU64,
+    /// Held by the creator while it initializes the mapping so openers notice when it dies
+    init_lock: UnsafeCell<[u64; 8]>,
+}
+
+const _: () = assert!(std::mem::size_of::<Header>() <= HEADER_SIZE);
+
+impl Header {
+    /// Casts the start of a mapping to its header
+    ///
+    /// # Safety
+    /// `ptr` must be aligned and point to at least `HEADER_SIZE` bytes that live as long as the header is used
+    pub unsafe fn from_ptr<'a>(ptr: *mut u8) -> &'a Header {
+        &*(ptr as *const Header)
+    }
+
+    /// Returns the size of the user data that follows the header
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed) as usize
+    }
+
+    /// Returns how many times the mapping was resized
+    pub fn generation(&self) -> u32 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Publishes a new size for the user data and returns the new generation
+    pub fn set_size(&self, size: usize) -> u32 {
+        self.size.store(size as u64, Ordering::Relaxed);
+        self.generation
+            .fetch_add(1, Ordering::Release)
+            .wrapping_add(1)
+    }
+
+    /// Records the address every process should map the mapping at
+    pub fn set_base_address(&self, addr: usize) {
+        self.base_address.store(addr as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the address recorded by the creator, if any
+    pub fn base_address(&self) -> Option<usize> {
+        match self.base_address.load(Ordering::Relaxed) {
+            0 => None,
+            addr => Some(addr as usize),
+        }
+    }
+
+    /// Returns whether the creator is still running the init closure
+    pub fn is_initializing(&self) -> bool {
+        self.state.load(Ordering::Acquire) == STATE_INITIALIZING
+    }
+
+    /// Writes the layout of the mapping and marks it as being initialized by the current process
+    ///
+    /// The mapping is flagged as poisoned if the returned guard is dropped before calling `finish()`
+    pub fn begin_init(&self, schema: u64, size: usize) -> InitGuard<'_> {
+        self.magic.store(HEADER_MAGIC, Ordering::Relaxed);
+        self.version.store(HEADER_VERSION, Ordering::Relaxed);
+        self.schema.store(schema, Ordering::Relaxed);
+        self.size.store(size as u64, Ordering::Relaxed);
+        // Openers only look at the lock once the state says it is held
+        let locked = unsafe { os_impl::lock_init(self.init_lock.get() as *mut u8) };
+        self.state.store(STATE_INITIALIZING, Ordering::Release);
+        InitGuard {
+            header: self,
+            locked,
+        }
+    }
+
+    /// Blocks until the creator has marked the mapping as ready
+    ///
+    /// Read-only openers cannot take the init lock or poison the header, they only rely on `timeout`
+    /// to detect an abandoned init
+    pub fn wait_ready(&self, timeout: Duration, read_only: bool) -> Result<(), ShmemError> {
+        let start = Instant::now();
+        loop {
+            let state = self.state.load(Ordering::Acquire);
+            if state != STATE_UNINIT {
+                self.check_format()?;
+            }
+            match state {
+                STATE_READY => return Ok(()),
+                STATE_POISONED => return Err(ShmemError::InitAbandoned),
+                STATE_INITIALIZING if !read_only => {
+                    if unsafe { os_impl::init_abandoned(self.init_lock.get() as *mut u8) } {
+                        debug!("Creator died while initializing the mapping");
+                        // Spare the other openers from checking the lock
+                        let _ = self.state.compare_exchange(
+                            STATE_INITIALIZING,
+                            STATE_POISONED,
+                            Ordering::AcqRel,
+                            Ordering::Relaxed,
+                        );
+                        return Err(ShmemError::InitAbandoned);
+                    }
+                }
+                STATE_UNINIT | STATE_INITIALIZING => {}
+                _ => return Err(ShmemError::HeaderInvalid),
+            }
+
+            if start.elapsed() >= timeout {
+                return Err(ShmemError::InitTimeout);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Validates that the header was written by a compatible version of this crate
+    fn check_format(&self) -> Result<(), ShmemError> {
+        if self.magic.load(Ordering::Relaxed) != HEADER_MAGIC {
+            return Err(ShmemError::HeaderInvalid);
+        }
+        let version = self.version.load(Ordering::Relaxed);
+        if version != HEADER_VERSION {
+            return Err(ShmemError::HeaderVersionMismatch {
+                expected: HEADER_VERSION,
+                found: version,
+            });
+        }
+        Ok(())
+    }
+
+    /// Validates the layout of a ready mapping against what the opener expects
+    pub fn check_layout(&self, schema: u64, map_size: usize) -> Result<(), ShmemError> {
+        let found = self.schema.load(Ordering::Relaxed);
+        if found != schema {
+            return Err(ShmemError::SchemaMismatch {
+                expected: schema,
+                found,
+            });
+        }
+        // The object might have been truncated behind our back
+        let required = HEADER_SIZE + self.size();
+        if map_size < required {
+            return Err(ShmemError::MapTooSmall {
+                required,
+                actual: map_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Poisons the mapping if the creator unwinds before finishing the initialization
+pub struct InitGuard<'a> {
+    header: &'a Header,
+    /// Whether the init lock of the header is held
+    locked: bool,
+}
+
+impl InitGuard<'_> {
+    /// Marks the mapping as ready to be used by other processes
+    pub fn finish(self) {
+        self.release(STATE_READY);
+        std::mem::forget(self);
+    }
+
+    /// Publishes the final state of the mapping then releases the init lock
+    fn release(&self, state: u32) {
+        self.header.state.store(state, Ordering::Release);
+        if self.locked {
+            unsafe { os_impl::unlock_init(self.header.init_lock.get() as *mut u8) };
+        }
+    }
+}
+
+impl Drop for InitGuard<'_> {
+    fn drop(&mut self) {
+        debug!("Initialization of the mapping did not complete");
+        self.release(STATE_POISONED);
+    }
+}
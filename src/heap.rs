@@ -0,0 +1,132 @@
+//! Backend that simulates shared memory with heap allocations of the current process
+
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use crate::log::*;
+use crate::{ShmemBackend, ShmemError, ShmemMapping};
+
+/// Alignment of the segments, this mimics the page alignment of real mappings
+const SEGMENT_ALIGN: usize = 4096;
+
+/// Segments that can currently be opened, by name
+static REGISTRY: Mutex<BTreeMap<String, Arc<Segment>>> = Mutex::new(BTreeMap::new());
+
+/// Backend that keeps mappings on the heap of the current process
+///
+/// Segments are registered by os_id in a process-global registry and follow the POSIX semantics
+/// of `shm_unlink()` : once the owner drops its mapping, the segment can no longer be opened but the memory
+/// stays valid until every mapping of it is dropped. Nothing touches the OS which makes it suitable for
+/// unit tests and Miri. Read-only mappings are not enforced.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapBackend;
+
+struct Segment {
+    ptr: *mut u8,
+    layout: Layout,
+}
+// The memory is only accessed through raw pointers handed out to the users of the mappings
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) };
+    }
+}
+
+struct HeapMapping {
+    os_id: String,
+    segment: Arc<Segment>,
+}
+
+unsafe impl ShmemMapping for HeapMapping {
+    fn os_id(&self) -> &str {
+        self.os_id.as_str()
+    }
+
+    fn len(&self) -> usize {
+        self.segment.layout.size()
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.segment.ptr
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no heap segment with this id")
+}
+
+impl ShmemBackend for HeapBackend {
+    fn create(&self, os_id: &str, size: usize) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        if size == 0 {
+            return Err(ShmemError::MapSizeZero);
+        }
+        let mut registry = REGISTRY.lock().unwrap();
+        if registry.contains_key(os_id) {
+            return Err(ShmemError::MappingIdExists);
+        }
+
+        let layout = match Layout::from_size_align(size, SEGMENT_ALIGN) {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(ShmemError::MapCreateFailed {
+                    syscall: "alloc",
+                    os_id: os_id.to_string(),
+                    source: io::Error::new(io::ErrorKind::InvalidInput, "invalid segment size"),
+                })
+            }
+        };
+        let ptr = unsafe { alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(ShmemError::MapCreateFailed {
+                syscall: "alloc",
+                os_id: os_id.to_string(),
+                source: io::Error::from(io::ErrorKind::OutOfMemory),
+            });
+        }
+        debug!("Created heap segment {} of {} bytes", os_id, size);
+
+        let segment = Arc::new(Segment { ptr, layout });
+        registry.insert(os_id.to_string(), segment.clone());
+        Ok(Box::new(HeapMapping {
+            os_id: os_id.to_string(),
+            segment,
+        }))
+    }
+
+    fn open(
+        &self,
+        os_id: &str,
+        _size: usize,
+        _read_only: bool,
+    ) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        match REGISTRY.lock().unwrap().get(os_id) {
+            Some(segment) => Ok(Box::new(HeapMapping {
+                os_id: os_id.to_string(),
+                segment: segment.clone(),
+            })),
+            None => Err(ShmemError::MapOpenFailed {
+                syscall: "open",
+                os_id: os_id.to_string(),
+                source: not_found(),
+            }),
+        }
+    }
+
+    fn unlink(&self, mapping: &dyn ShmemMapping) -> Result<(), ShmemError> {
+        let os_id = mapping.os_id();
+        // The memory is freed once the last mapping drops its reference
+        match REGISTRY.lock().unwrap().remove(os_id) {
+            Some(_) => Ok(()),
+            None => Err(ShmemError::OsError {
+                syscall: "unlink",
+                os_id: os_id.to_string(),
+                source: not_found(),
+            }),
+        }
+    }
+}
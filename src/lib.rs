@@ -4,9 +4,12 @@
 
 use std::fs::{File, OpenOptions};
 use std::io::{ErrorKind, Read, Write};
+use std::ops::Range;
 
 use std::fs::remove_file;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use cfg_if::cfg_if;
 
@@ -31,19 +34,70 @@ use crate::log::*;
 mod error;
 pub use error::*;
 
+mod header;
+use header::*;
+
+mod backend;
+pub use backend::*;
+
+mod heap;
+pub use heap::HeapBackend;
+
+#[cfg(feature = "fault-injection")]
+mod fault;
+#[cfg(feature = "fault-injection")]
+pub use fault::{FaultBackend, FaultPlan};
+
 //Load up the proper OS implementation
 cfg_if! {
     if #[cfg(target_os="windows")] {
         mod windows;
         use windows as os_impl;
     } else if #[cfg(any(target_os="freebsd", target_os="linux", target_os="macos"))] {
+        mod sysv;
         mod unix;
         use crate::unix as os_impl;
+        pub use unix::{Advice, Protection};
+        #[cfg(target_os="linux")]
+        pub use unix::HugePageSize;
     } else {
         compile_error!("shared_memory isnt implemented for this platform...");
     }
 }
 
+type InitFn = dyn Fn(&mut Shmem) + Send + Sync;
+
+/// Calls `remove` to delete the object that was just created when setting it up failed
+///
+/// Nobody owns the object yet, it would leak if it was not removed here
+fn discard_on_error<T, R>(
+    res: Result<T, ShmemError>,
+    remove: impl FnOnce() -> R,
+) -> Result<T, ShmemError> {
+    if res.is_err() {
+        let _ = remove();
+    }
+    res
+}
+
+/// Failures planned with `ShmemConf::fault_plan()` for the I/O on file links and the syscalls of the OS backend
+///
+/// Nothing is ever injected without the `fault-injection` feature
+#[derive(Clone, Default)]
+pub(crate) struct Faults(#[cfg(feature = "fault-injection")] Option<FaultPlan>);
+
+impl Faults {
+    /// Fails with the error planned for `point`, if any
+    #[cfg_attr(not(feature = "fault-injection"), allow(unused_variables))]
+    fn check(&self, point: &str) -> std::io::Result<()> {
+        #[cfg(feature = "fault-injection")]
+        if let Some(e) = self.0.as_ref().and_then(|plan| plan.take(point)) {
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Default)]
 /// Struct used to configure different parameters before creating a shared memory mapping
 pub struct ShmemConf {
@@ -52,6 +106,18 @@ pub struct ShmemConf {
     overwrite_flink: bool,
     flink_path: Option<PathBuf>,
     size: usize,
+    offset: Option<u64>,
+    read_only: bool,
+    header: bool,
+    schema: u64,
+    init: Option<Arc<InitFn>>,
+    init_timeout: Option<Duration>,
+    fixed_address: Option<usize>,
+    same_address: bool,
+    private: bool,
+    backend: Option<Arc<dyn ShmemBackend>>,
+    #[cfg(feature = "fault-injection")]
+    fault_plan: Option<FaultPlan>,
     ext: os_impl::ShmemConfExt,
 }
 impl Drop for ShmemConf {
@@ -100,11 +166,197 @@ impl ShmemConf {
         self
     }
 
+    /// Maps a window of the object that starts at `offset` instead of all of it
+    ///
+    /// `offset` must be a multiple of the page size, 0 included. The window spans `size()` bytes, or up to the end of
+    /// the object when no size is set, and `open()` fails with `ShmemError::MapTooSmall` when the object does not cover it.
+    /// This only applies to `open()`. Such partial views cannot be resized nor refreshed and cannot be used along
+    /// with the managed header, which lives at the start of the object
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Opens the mapping with read-only access
+    ///
+    /// The mapping is opened and mapped without write permissions, which means the calling process
+    /// only needs read access to the underlying object. This only applies to `open()`, mappings returned
+    /// by `create()` are always writable.
+    ///
+    /// `Shmem::as_slice_mut()` returns `None` for such mappings. Writing through `Shmem::as_ptr()` to a read-only
+    /// mapping crashes the process with an access violation, see `Shmem::is_read_only()`.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Reserves a header managed by this crate at the start of the mapping
+    ///
+    /// The header identifies the mapping, the format version, the user schema and the size of the data.
+    /// `open()` validates it and returns an error if it does not match, which prevents opening unrelated objects.
+    /// `as_ptr()` and `len()` only describe the data that follows the header.
+    ///
+    /// The creator and the openers must all enable it. It is also enabled by `init()`, `init_timeout()` and `schema()`
+    pub fn managed_header(mut self) -> Self {
+        self.header = true;
+        self
+    }
+
+    /// Sets an identifier for the layout of the data stored in the mapping
+    ///
+    /// This is typically a version number or a hash of the types shared through the mapping.
+    /// `open()` fails with `ShmemError::SchemaMismatch` when it differs from the one set by the creator.
+    /// This enables the managed header, see `managed_header()`
+    pub fn schema(mut self, schema: u64) -> Self {
+        self.header = true;
+        self.schema = schema;
+        self
+    }
+
+    /// Sets a closure that initializes the mapping when it gets created
+    ///
+    /// Processes that open the mapping block until the creator has finished running the closure, see `init_timeout()`.
+    /// They fail with `ShmemError::InitAbandoned` when the closure panics, or on Linux when the creator dies while
+    /// running it. This enables the managed header, see `managed_header()`
+    pub fn init<F: Fn(&mut Shmem) + Send + Sync + 'static>(mut self, init: F) -> Self {
+        self.header = true;
+        self.init = Some(Arc::new(init));
+        self
+    }
+
+    /// Sets how long `open()` waits for the creator to initialize the mapping
+    ///
+    /// Defaults to 5 seconds. This enables the managed header, see `managed_header()`
+    pub fn init_timeout(mut self, timeout: Duration) -> Self {
+        self.header = true;
+        self.init_timeout = Some(timeout);
+        self
+    }
+
+    /// Maps the mapping at `addr` in our address space
+    ///
+    /// `addr` must be page aligned. `create()` and `open()` fail with `ShmemError::AddressUnavailable` when something
+    /// else is mapped there, nothing gets replaced. `Shmem::resize()` and `Shmem::refresh()` keep the mapping at
+    /// this address. Only supported by the unix backends
+    pub fn fixed_address(mut self, addr: usize) -> Self {
+        self.fixed_address = Some(addr);
+        self
+    }
+
+    /// Maps the mapping at the same address in every process
+    ///
+    /// The creator records the address of its mapping, picked by the OS unless `fixed_address()` is set, and `open()`
+    /// maps the mapping at that same address or fails with `ShmemError::AddressUnavailable`, ignoring `fixed_address()`.
+    /// This allows storing raw pointers into the mapping inside of it. The creator and the openers must all enable it.
+    /// This enables the managed header, see `managed_header()`
+    pub fn same_address(mut self) -> Self {
+        self.header = true;
+        self.same_address = true;
+        self
+    }
+
+    /// Uses a custom backend to create, open and remove the mapping
+    ///
+    /// When not specified, the OS shared memory facilities are used, see `ShmemBackend`
+    pub fn backend<B: ShmemBackend + 'static>(mut self, backend: B) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Injects the failures scripted in `plan` into the backend of the mapping
+    ///
+    /// The backend, either the default one or the one set with `backend()`, gets wrapped in a `FaultBackend`.
+    /// The I/O on file links and the syscalls made by the OS backend fail according to the same plan.
+    /// Only available with the `fault-injection` feature
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_plan(mut self, plan: FaultPlan) -> Self {
+        self.ext.faults = Faults(Some(plan.clone()));
+        self.fault_plan = Some(plan);
+        self
+    }
+
+    /// Returns the backend of the mapping, resolving the default one if needed
+    fn get_backend(&mut self) -> Arc<dyn ShmemBackend> {
+        let ext = &self.ext;
+        let backend = self
+            .backend
+            .get_or_insert_with(|| os_impl::default_backend(ext));
+        #[cfg(feature = "fault-injection")]
+        if let Some(plan) = self.fault_plan.take() {
+            *backend = Arc::new(FaultBackend::wrap(backend.clone(), plan));
+        }
+        backend.clone()
+    }
+
+    /// Creates the flink and writes the os_id of the mapping to it
+    fn create_flink(&self, flink_path: &Path, os_id: &str) -> Result<(), ShmemError> {
+        debug!("Creating file link that points to mapping");
+        let mut open_options: OpenOptions = OpenOptions::new();
+        open_options.write(true);
+
+        if self.overwrite_flink {
+            open_options.create(true).truncate(true);
+        } else {
+            open_options.create_new(true);
+        }
+
+        let faults = &self.ext.faults;
+        match faults
+            .check("flink_create")
+            .and_then(|_| open_options.open(flink_path))
+        {
+            Ok(mut f) => {
+                // write the shmem uid asap
+                if let Err(e) = faults
+                    .check("flink_write")
+                    .and_then(|_| f.write_all(os_id.as_bytes()))
+                {
+                    let _ = std::fs::remove_file(flink_path);
+                    return Err(ShmemError::LinkWriteFailed(e));
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(ShmemError::LinkExists),
+            Err(e) => return Err(ShmemError::LinkCreateFailed(e)),
+        }
+
+        debug!(
+            "Created file link '{}' with id '{}'",
+            flink_path.to_string_lossy(),
+            os_id
+        );
+        Ok(())
+    }
+
+    /// Returns the window of the object that `open()` maps, None to map all of it
+    fn window(&self) -> Option<(u64, usize)> {
+        self.offset.map(|offset| (offset, self.size))
+    }
+
+    /// Checks that the window can be opened with the current configuration
+    fn check_window(&self) -> Result<(), ShmemError> {
+        if self.offset.is_some() && self.header {
+            return Err(ShmemError::Unsupported(
+                "Opening part of a mapping with a managed header",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the amount of bytes reserved at the start of the mapping
+    fn header_size(&self) -> usize {
+        if self.header {
+            HEADER_SIZE
+        } else {
+            0
+        }
+    }
+
     /// Create a new mapping using the current configuration
     pub fn create(mut self) -> Result<Shmem, ShmemError> {
         if self.size == 0 {
             return Err(ShmemError::MapSizeZero);
         }
+        let map_size = self.size + self.header_size();
 
         if let Some(ref flink_path) = self.flink_path {
             if !self.overwrite_flink && flink_path.is_file() {
@@ -113,12 +365,13 @@ impl ShmemConf {
         }
 
         // Create the mapping
-        let mapping = match self.os_id {
+        let backend = self.get_backend();
+        let mut mapping = match self.os_id {
             None => {
                 // Generate random ID until one works
                 loop {
                     let cur_id = format!("/shmem_{:X}", rand::random::<u64>());
-                    match os_impl::create_mapping(&cur_id, self.size) {
+                    match backend.create(&cur_id, map_size) {
                         Err(ShmemError::MappingIdExists) => continue,
                         Ok(m) => break m,
                         Err(e) => {
@@ -127,50 +380,109 @@ impl ShmemConf {
                     };
                 }
             }
-            Some(ref specific_id) => os_impl::create_mapping(specific_id, self.size)?,
+            Some(ref specific_id) => backend.create(specific_id, map_size)?,
         };
-        debug!("Created shared memory mapping '{}'", mapping.unique_id);
+        debug!("Created shared memory mapping '{}'", mapping.os_id());
+
+        if let Some(addr) = self.fixed_address {
+            let res = mapping.remap_at(addr);
+            discard_on_error(res, || backend.unlink(mapping.as_ref()))?;
+        }
+        if self.same_address {
+            self.fixed_address = Some(mapping.as_ptr() as usize);
+        }
 
         // Create flink
         if let Some(ref flink_path) = self.flink_path {
-            debug!("Creating file link that points to mapping");
-            let mut open_options: OpenOptions = OpenOptions::new();
-            open_options.write(true);
+            let res = self.create_flink(flink_path, mapping.os_id());
+            discard_on_error(res, || backend.unlink(mapping.as_ref()))?;
+        }
 
-            if self.overwrite_flink {
-                open_options.create(true).truncate(true);
-            } else {
-                open_options.create_new(true);
+        self.owner = true;
+        self.read_only = false;
+        self.size = mapping.len();
+
+        let mut shmem = Shmem {
+            config: self,
+            mapping,
+            generation: 0,
+        };
+        if shmem.config.header {
+            // The OS might have rounded the size up, let the user data cover all of it
+            shmem.run_init(shmem.mapping.len() - HEADER_SIZE);
+        }
+        Ok(shmem)
+    }
+
+    /// Opens the mapping if it exists or creates it otherwise
+    ///
+    /// Returns the mapping along with whether this call created it. This handles the races that
+    /// happen when another process creates, opens or drops the same mapping concurrently.
+    /// A flink that points to a mapping that no longer exists (e.g. its owner crashed) is not removed,
+    /// the open error is returned after a few attempts instead.
+    pub fn open_or_create(self) -> Result<(Shmem, bool), ShmemError> {
+        let mut retry = 0;
+        loop {
+            match self.clone().create() {
+                Ok(m) => return Ok((m, true)),
+                Err(ShmemError::LinkExists) | Err(ShmemError::MappingIdExists) => {}
+                Err(e) => return Err(e),
             }
+            debug!("Mapping already exists, trying to open it");
 
-            match open_options.open(flink_path) {
-                Ok(mut f) => {
-                    // write the shmem uid asap
-                    if let Err(e) = f.write(mapping.unique_id.as_bytes()) {
-                        let _ = std::fs::remove_file(flink_path);
-                        return Err(ShmemError::LinkWriteFailed(e));
-                    }
-                }
-                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
-                    return Err(ShmemError::LinkExists)
+            match self.clone().open() {
+                Ok(m) => return Ok((m, false)),
+                // The creator has not set the size of the mapping yet
+                Err(ShmemError::MapSizeZero) if retry < 5 => {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
                 }
-                Err(e) => return Err(ShmemError::LinkCreateFailed(e)),
+                // The owner dropped the mapping between our calls, try to create it again
+                Err(e) if e.kind() == ShmemErrorKind::NotFound && retry < 5 => {}
+                Err(e) => return Err(e),
             }
-
-            debug!(
-                "Created file link '{}' with id '{}'",
-                flink_path.to_string_lossy(),
-                mapping.unique_id
-            );
+            retry += 1;
         }
+    }
 
-        self.owner = true;
-        self.size = mapping.map_size;
+    /// Wraps a mapping that was just opened and waits for it to be initialized
+    fn into_opened(mut self, mapping: Box<dyn ShmemMapping>) -> Result<Shmem, ShmemError> {
+        let requested_size = self.size;
+        self.size = mapping.len();
+        self.owner = false;
+        // Remember the backend so the mapping can be removed if we take ownership of it
+        self.get_backend();
+        if self.same_address {
+            // The address recorded by the creator is used instead
+            self.fixed_address = None;
+        }
 
-        Ok(Shmem {
+        let mut shmem = Shmem {
             config: self,
             mapping,
-        })
+            generation: 0,
+        };
+        if let Some(addr) = shmem.config.fixed_address {
+            shmem.pin_address(addr)?;
+        }
+        if shmem.config.header {
+            shmem.wait_init(requested_size)?;
+        } else if requested_size > shmem.mapping.len() {
+            return Err(ShmemError::MapTooSmall {
+                required: requested_size,
+                actual: shmem.mapping.len(),
+            });
+        }
+        if shmem.config.same_address {
+            let addr =
+                match shmem.header().and_then(|h| h.base_address()) {
+                    Some(addr) => addr,
+                    None => return Err(ShmemError::Unsupported(
+                        "Mapping at the same address as a creator that did not use same_address()",
+                    )),
+                };
+            shmem.pin_address(addr)?;
+        }
+        Ok(shmem)
     }
 
     /// Opens an existing mapping using the current configuration
@@ -180,7 +492,9 @@ impl ShmemConf {
             debug!("Open called with no file link or unique id...");
             return Err(ShmemError::NoLinkOrOsId);
         }
+        self.check_window()?;
 
+        let backend = self.get_backend();
         let mut flink_uid = String::new();
         let mut retry = 0;
         loop {
@@ -193,30 +507,38 @@ impl ShmemConf {
                     "Open shared memory from file link {}",
                     flink_path.to_string_lossy()
                 );
-                let mut f = match File::open(flink_path) {
+                let mut f = match self
+                    .ext
+                    .faults
+                    .check("flink_open")
+                    .and_then(|_| File::open(flink_path))
+                {
                     Ok(f) => f,
                     Err(e) => return Err(ShmemError::LinkOpenFailed(e)),
                 };
                 flink_uid.clear();
-                if let Err(e) = f.read_to_string(&mut flink_uid) {
+                if let Err(e) = self
+                    .ext
+                    .faults
+                    .check("flink_read")
+                    .and_then(|_| f.read_to_string(&mut flink_uid))
+                {
                     return Err(ShmemError::LinkReadFailed(e));
                 }
                 flink_uid.as_str()
             };
 
-            match os_impl::open_mapping(unique_id, self.size, &self.ext) {
-                Ok(m) => {
-                    self.size = m.map_size;
-                    self.owner = false;
-
-                    return Ok(Shmem {
-                        config: self,
-                        mapping: m,
-                    });
+            let res = match self.window() {
+                None => backend.open(unique_id, self.size, self.read_only),
+                Some((offset, size)) => {
+                    backend.open_window(unique_id, offset, size, self.read_only)
                 }
+            };
+            match res {
+                Ok(m) => return self.into_opened(m),
                 // If we got this failing os_id from the flink, try again in case the shmem owner didnt write the full
                 // unique_id to the file
-                Err(ShmemError::MapOpenFailed(_)) if self.os_id.is_none() && retry < 5 => {
+                Err(ShmemError::MapOpenFailed { .. }) if self.os_id.is_none() && retry < 5 => {
                     retry += 1;
                     std::thread::sleep(std::time::Duration::from_millis(50));
                 }
@@ -229,10 +551,95 @@ impl ShmemConf {
 /// Structure used to extract information from an existing shared memory mapping
 pub struct Shmem {
     config: ShmemConf,
-    mapping: os_impl::MapData,
+    mapping: Box<dyn ShmemMapping>,
+    /// Generation of the header our mapping was last sized for
+    generation: u32,
 }
 #[allow(clippy::len_without_is_empty)]
 impl Shmem {
+    /// Returns the header reserved at the start of the mapping
+    fn header(&self) -> Option<&Header> {
+        if self.config.header {
+            Some(unsafe { Header::from_ptr(self.mapping.as_ptr()) })
+        } else {
+            None
+        }
+    }
+    /// Returns whether the init closure is still running on the mapping
+    fn initializing(&self) -> bool {
+        self.header().is_some_and(|header| header.is_initializing())
+    }
+    /// Writes the header, runs the init closure and marks the mapping as ready
+    fn run_init(&mut self, size: usize) {
+        // The header lives as long as the mapping, which outlives the init closure
+        let header = unsafe { Header::from_ptr(self.mapping.as_ptr()) };
+        let guard = header.begin_init(self.config.schema, size);
+        if self.config.same_address {
+            header.set_base_address(self.mapping.as_ptr() as usize);
+        }
+        if let Some(init) = self.config.init.clone() {
+            debug!("Running init closure on new mapping");
+            init(self);
+        }
+        guard.finish();
+    }
+    /// Waits for the creator of the mapping to finish initializing it and validates the header
+    fn wait_init(&mut self, requested_size: usize) -> Result<(), ShmemError> {
+        if self.mapping.len() < HEADER_SIZE {
+            return Err(ShmemError::MapTooSmall {
+                required: HEADER_SIZE,
+                actual: self.mapping.len(),
+            });
+        }
+        if let Some(header) = self.header() {
+            debug!("Waiting for mapping to be initialized");
+            header.wait_ready(
+                self.config.init_timeout.unwrap_or(DEFAULT_INIT_TIMEOUT),
+                self.config.read_only,
+            )?;
+        }
+        // The mapping might have been resized since we looked at its size
+        self.refresh()?;
+        if let Some(header) = self.header() {
+            header.check_layout(self.config.schema, self.mapping.len())?;
+            if requested_size > header.size() {
+                return Err(ShmemError::MapTooSmall {
+                    required: requested_size,
+                    actual: header.size(),
+                });
+            }
+        }
+        Ok(())
+    }
+    /// Moves the mapping to `addr` and keeps it there when it gets remapped
+    fn pin_address(&mut self, addr: usize) -> Result<(), ShmemError> {
+        if self.mapping.as_ptr() as usize != addr {
+            debug!("Moving mapping {} to {:#x}", self.get_os_id(), addr);
+            self.mapping.remap_at(addr)?;
+        }
+        self.config.fixed_address = Some(addr);
+        Ok(())
+    }
+    /// Moves the mapping back to its fixed address if remapping it moved it
+    fn restore_address(&mut self) -> Result<(), ShmemError> {
+        match self.config.fixed_address {
+            Some(addr) => self.pin_address(addr),
+            None => Ok(()),
+        }
+    }
+    /// Converts a range of the user data into a range of the mapping
+    #[cfg_attr(windows, allow(dead_code))]
+    fn map_range(&self, range: Range<usize>) -> Result<Range<usize>, ShmemError> {
+        if range.start > range.end || range.end > self.len() {
+            return Err(ShmemError::RangeOutOfBounds {
+                start: range.start,
+                end: range.end,
+                len: self.len(),
+            });
+        }
+        let offset = self.config.header_size();
+        Ok(range.start + offset..range.end + offset)
+    }
     /// Returns whether we created the mapping or not
     pub fn is_owner(&self) -> bool {
         self.config.owner
@@ -241,27 +648,155 @@ impl Shmem {
     ///
     /// Warning : You must ensure at least one process owns the mapping in order to ensure proper cleanup code is ran
     pub fn set_owner(&mut self, is_owner: bool) -> bool {
-        self.mapping.set_owner(is_owner);
-
         let prev_val = self.config.owner;
         self.config.owner = is_owner;
         prev_val
     }
     /// Returns the OS unique identifier for the mapping
     pub fn get_os_id(&self) -> &str {
-        self.mapping.unique_id.as_str()
+        self.mapping.os_id()
     }
     /// Returns the flink path if present
     pub fn get_flink_path(&self) -> Option<&PathBuf> {
         self.config.flink_path.as_ref()
     }
+    /// Returns whether the mapping was opened with read-only access
+    pub fn is_read_only(&self) -> bool {
+        self.config.read_only
+    }
     /// Returns the total size of the mapping
+    ///
+    /// This only covers the data that follows the header when `ShmemConf::managed_header()` is used
     pub fn len(&self) -> usize {
-        self.mapping.map_size
+        match self.header() {
+            // A peer might have grown the mapping before we called refresh()
+            Some(header) => header
+                .size()
+                .min(self.mapping.len().saturating_sub(HEADER_SIZE)),
+            None => self.mapping.len(),
+        }
+    }
+    /// Changes the size of the mapping
+    ///
+    /// The mapping might move in our address space so pointers previously returned by `as_ptr()` are invalidated,
+    /// unless it was mapped with `ShmemConf::fixed_address()` or `ShmemConf::same_address()`.
+    /// Other processes keep their current view until they call `refresh()`, see `needs_refresh()`.
+    /// Shrinking a mapping while other processes still access the truncated range makes them crash with `SIGBUS`.
+    ///
+    /// Not supported on Windows, for read-only mappings nor from the init closure.
+    /// MacOS only allows setting the size of an object once.
+    pub fn resize(&mut self, new_len: usize) -> Result<(), ShmemError> {
+        if new_len == 0 {
+            return Err(ShmemError::MapSizeZero);
+        }
+        if self.is_read_only() {
+            return Err(ShmemError::Unsupported("Resizing read-only mappings"));
+        }
+        // The header is still written to when the init closure returns
+        if self.initializing() {
+            return Err(ShmemError::Unsupported(
+                "Resizing mappings during their init closure",
+            ));
+        }
+        if self.config.window().is_some() {
+            return Err(ShmemError::Unsupported("Resizing partial views"));
+        }
+        if self.config.private {
+            return Err(ShmemError::Unsupported("Resizing private views"));
+        }
+        debug!("Resizing mapping {} to {}", self.get_os_id(), new_len);
+        if let Err(e) = self.mapping.resize(new_len + self.config.header_size()) {
+            // The mapping might have moved before the resize failed
+            let _ = self.restore_address();
+            return Err(e);
+        }
+        self.restore_address()?;
+        self.config.size = self.mapping.len();
+        if let Some(header) = self.header() {
+            // The OS might have rounded the size up like in create()
+            self.generation = header.set_size(self.mapping.len() - HEADER_SIZE);
+        }
+        Ok(())
+    }
+    /// Returns whether another process resized the mapping since our last call to `refresh()`
+    ///
+    /// With a managed header, this only reads the generation counter from the header.
+    /// Otherwise, the size of the underlying object is queried from the OS.
+    pub fn needs_refresh(&self) -> bool {
+        if self.config.window().is_some() || self.config.private {
+            return false;
+        }
+        match self.header() {
+            Some(header) => header.generation() != self.generation,
+            None => self
+                .mapping
+                .object_size()
+                .is_ok_and(|size| size != self.mapping.len()),
+        }
+    }
+    /// Remaps the mapping if another process resized it
+    ///
+    /// Returns whether the mapping was remapped, in which case pointers previously returned by `as_ptr()` are invalidated
+    pub fn refresh(&mut self) -> Result<bool, ShmemError> {
+        // Partial and private views keep the size they were opened with
+        if self.config.window().is_some() || self.config.private {
+            return Ok(false);
+        }
+        // The header is still written to when the init closure returns
+        if self.initializing() {
+            return Err(ShmemError::Unsupported(
+                "Remapping mappings during their init closure",
+            ));
+        }
+        // Read the generation first so a concurrent resize is always noticed by the next call
+        let generation = self.header().map(|h| h.generation());
+        let size = self.mapping.object_size()?;
+        let remapped = size != self.mapping.len();
+        if remapped {
+            debug!(
+                "Remapping {} from {} to {} bytes",
+                self.get_os_id(),
+                self.mapping.len(),
+                size
+            );
+            self.mapping.remap(size)?;
+            self.restore_address()?;
+            self.config.size = size;
+        }
+        if let Some(generation) = generation {
+            self.generation = generation;
+        }
+        Ok(remapped)
+    }
+    /// Maps the mapping again as a private copy-on-write view
+    ///
+    /// Writes to the view stay in this process, neither the object nor other mappings of it see them.
+    /// This is not a snapshot : a page is only copied the first time the view writes to it, until then the view reads
+    /// the changes other processes make to that page. Copy what must not change into the view before relying on it.
+    ///
+    /// The view never owns the mapping, is always writable and cannot be resized.
+    /// Not supported on Windows nor for System V segments.
+    pub fn private_view(&self) -> Result<Shmem, ShmemError> {
+        debug!("Mapping a private view of {}", self.get_os_id());
+        let mapping = self.mapping.private_view()?;
+        let mut config = self.config.clone();
+        config.owner = false;
+        config.read_only = false;
+        config.private = true;
+        // The view lives next to this mapping, it cannot share its address
+        config.fixed_address = None;
+        Ok(Shmem {
+            config,
+            mapping,
+            generation: self.generation,
+        })
     }
     /// Returns a raw pointer to the mapping
+    ///
+    /// This points past the header when `ShmemConf::managed_header()` is used.
+    /// The pointer must not be written through when the mapping is read-only, see `is_read_only()`
     pub fn as_ptr(&self) -> *mut u8 {
-        self.mapping.as_mut_ptr()
+        unsafe { self.mapping.as_ptr().add(self.config.header_size()) }
     }
     /// Returns mapping as a byte slice
     /// # Safety
@@ -269,10 +804,26 @@ impl Shmem {
     pub unsafe fn as_slice(&self) -> &[u8] {
         std::slice::from_raw_parts(self.as_ptr(), self.len())
     }
-    /// Returns mapping as a mutable byte slice
+    /// Returns mapping as a mutable byte slice, or `None` if the mapping is read-only
     /// # Safety
     /// This function is unsafe because it is impossible to ensure the returned mutable refence is unique/exclusive
-    pub unsafe fn as_slice_mut(&mut self) -> &mut [u8] {
-        std::slice::from_raw_parts_mut(self.as_ptr(), self.len())
+    pub unsafe fn as_slice_mut(&mut self) -> Option<&mut [u8]> {
+        if self.is_read_only() {
+            return None;
+        }
+        Some(std::slice::from_raw_parts_mut(self.as_ptr(), self.len()))
+    }
+}
+
+impl Drop for Shmem {
+    fn drop(&mut self) {
+        // Remove the object if we are the owner of the mapping, it stays mapped until self.mapping is dropped
+        if self.config.owner {
+            if let Some(backend) = self.config.backend.as_ref() {
+                if let Err(_e) = backend.unlink(self.mapping.as_ref()) {
+                    debug!("Failed to remove mapping {} : {}", self.mapping.os_id(), _e);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,147 @@
+//! Thin wrappers around the System V shared memory calls
+
+use std::ops::Range;
+use std::ptr::null;
+
+use nix::errno::Errno;
+
+use crate::log::*;
+use crate::{Protection, ShmemError, ShmemMapping};
+
+/// A System V segment attached to our address space
+pub struct SysvMapping {
+    pub shmid: libc::c_int,
+    pub unique_id: String,
+    pub map_size: usize,
+    pub map_ptr: *mut u8,
+}
+
+impl Drop for SysvMapping {
+    fn drop(&mut self) {
+        if !self.map_ptr.is_null() {
+            if let Err(_e) = detach(self.map_ptr) {
+                debug!("Failed to shmdt() shared memory mapping : {}", _e);
+            }
+        }
+    }
+}
+
+unsafe impl ShmemMapping for SysvMapping {
+    fn os_id(&self) -> &str {
+        self.unique_id.as_str()
+    }
+
+    fn len(&self) -> usize {
+        self.map_size
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.map_ptr
+    }
+
+    fn protect(&self, range: Range<usize>, protection: Protection) -> Result<(), ShmemError> {
+        let addr = unsafe { self.map_ptr.add(range.start) };
+        protection
+            .apply(addr, range.len())
+            .map_err(|e| ShmemError::OsError {
+                syscall: "mprotect",
+                os_id: self.unique_id.clone(),
+                source: e.into(),
+            })
+    }
+
+    fn unlink(&self) -> Result<(), ShmemError> {
+        debug!("Deleting System V segment");
+        // Removing by id, the key might already name another segment if ours was removed behind our back
+        remove(self.shmid).map_err(|e| ShmemError::OsError {
+            syscall: "shmctl",
+            os_id: self.unique_id.clone(),
+            source: e.into(),
+        })
+    }
+
+    fn object_size(&self) -> Result<usize, ShmemError> {
+        match stat(self.shmid) {
+            Ok(ds) => Ok(ds.shm_segsz),
+            Err(e) => Err(ShmemError::OsError {
+                syscall: "shmctl",
+                os_id: self.unique_id.clone(),
+                source: e.into(),
+            }),
+        }
+    }
+}
+
+/// Parses the key of a segment from its os_id, either `0x` prefixed hexadecimal as printed by `ipcs` or decimal
+pub fn parse_key(os_id: &str) -> Option<libc::key_t> {
+    match os_id.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().map(|k| k as libc::key_t),
+        None => os_id.parse().ok(),
+    }
+}
+
+/// Formats a key the way `ipcs` does
+pub fn format_key(key: libc::key_t) -> String {
+    format!("{:#010x}", key)
+}
+
+/// Creates a new segment, failing if one already exists for this key
+pub fn create(key: libc::key_t, size: usize, mode: u32) -> nix::Result<libc::c_int> {
+    let flags = libc::IPC_CREAT | libc::IPC_EXCL | (mode & 0o777) as libc::c_int;
+    let shmid = Errno::result(unsafe { libc::shmget(key, size, flags) })?;
+    trace!("shmget({:#x}, {}, {:o}) == {}", key, size, flags, shmid);
+    Ok(shmid)
+}
+
+/// Looks up the existing segment for this key
+pub fn open(key: libc::key_t) -> nix::Result<libc::c_int> {
+    let shmid = Errno::result(unsafe { libc::shmget(key, 0, 0) })?;
+    trace!("shmget({:#x}, 0, 0) == {}", key, shmid);
+    Ok(shmid)
+}
+
+/// Returns the current state of the segment
+pub fn stat(shmid: libc::c_int) -> nix::Result<libc::shmid_ds> {
+    let mut ds: libc::shmid_ds = unsafe { std::mem::zeroed() };
+    Errno::result(unsafe { libc::shmctl(shmid, libc::IPC_STAT, &mut ds) })?;
+    Ok(ds)
+}
+
+/// Changes the owner of the segment
+pub fn chown(shmid: libc::c_int, uid: Option<u32>, gid: Option<u32>) -> nix::Result<()> {
+    let mut ds = stat(shmid)?;
+    if let Some(uid) = uid {
+        ds.shm_perm.uid = uid as _;
+    }
+    if let Some(gid) = gid {
+        ds.shm_perm.gid = gid as _;
+    }
+    trace!("shmctl({}, IPC_SET, {:?}, {:?})", shmid, uid, gid);
+    Errno::result(unsafe { libc::shmctl(shmid, libc::IPC_SET, &mut ds) })?;
+    Ok(())
+}
+
+/// Maps the segment into our address space
+pub fn attach(shmid: libc::c_int, read_only: bool) -> nix::Result<*mut u8> {
+    let flags = if read_only { libc::SHM_RDONLY } else { 0 };
+    let ptr = unsafe { libc::shmat(shmid, null(), flags) };
+    if ptr as isize == -1 {
+        return Err(Errno::last());
+    }
+    trace!("shmat({}, NULL, {:X}) == {:p}", shmid, flags, ptr);
+    Ok(ptr as *mut u8)
+}
+
+/// Unmaps the segment from our address space
+pub fn detach(ptr: *mut u8) -> nix::Result<()> {
+    trace!("shmdt({:p})", ptr);
+    Errno::result(unsafe { libc::shmdt(ptr as *const _) })?;
+    Ok(())
+}
+
+/// Marks the segment for removal, it is destroyed once every process has detached from it
+pub fn remove(shmid: libc::c_int) -> nix::Result<()> {
+    trace!("shmctl({}, IPC_RMID)", shmid);
+    Errno::result(unsafe { libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut()) })?;
+    Ok(())
+}
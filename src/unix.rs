@@ -1,66 +1,306 @@
+use std::convert::{TryFrom, TryInto};
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::io::{self, IoSlice, IoSliceMut};
 use std::num::NonZeroUsize;
-use std::os::unix::io::RawFd;
+use std::ops::Range;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::ptr::null_mut;
+use std::sync::Arc;
 
 use crate::log::*;
-use nix::fcntl::OFlag;
-use nix::sys::mman::{mmap, munmap, shm_open, shm_unlink, MapFlags, ProtFlags};
-use nix::sys::stat::{fstat, Mode};
-use nix::unistd::{close, ftruncate};
+#[cfg(target_os = "linux")]
+use nix::fcntl::SealFlag;
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
+#[cfg(target_os = "linux")]
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::sys::mman::{
+    madvise, mlock, mprotect, msync, munmap, shm_open, shm_unlink, MapFlags, MmapAdvise, MsFlags,
+    ProtFlags,
+};
+#[cfg(target_os = "linux")]
+use nix::sys::mman::{mremap, MRemapFlags};
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+#[cfg(not(target_os = "linux"))]
+use nix::sys::stat::fstat;
+use nix::sys::stat::{fchmod, Mode};
+use nix::sys::statvfs::{statvfs, Statvfs};
+use nix::unistd::{close, fchown, ftruncate, unlink, Gid, Uid};
 
-use crate::ShmemError;
+use crate::sysv::{self, SysvMapping};
+use crate::{
+    discard_on_error, Faults, Shmem, ShmemBackend, ShmemConf, ShmemError, ShmemErrorKind,
+    ShmemMapping,
+};
+
+/// Hints about how a range of a mapping will be used, see `Shmem::advise()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Advice {
+    /// The range will be accessed soon, the kernel can start reading it in
+    WillNeed,
+    /// The range will not be accessed soon, the kernel can drop it from our address space.
+    /// The contents of shared mappings are kept, see `Remove` to free them
+    DontNeed,
+    /// The range will be accessed sequentially, the kernel can read ahead more aggressively
+    Sequential,
+    /// Back the range with transparent huge pages when possible
+    #[cfg(target_os = "linux")]
+    HugePage,
+    /// Frees the pages and their contents, which read back as zeroes afterwards.
+    /// This punches a hole in the object backing the mapping for every process that maps it
+    #[cfg(target_os = "linux")]
+    Remove,
+    /// Do not share the range with child processes created by `fork()`
+    #[cfg(target_os = "linux")]
+    DontFork,
+}
+
+impl Advice {
+    fn as_madvise(self) -> MmapAdvise {
+        match self {
+            Advice::WillNeed => MmapAdvise::MADV_WILLNEED,
+            Advice::DontNeed => MmapAdvise::MADV_DONTNEED,
+            Advice::Sequential => MmapAdvise::MADV_SEQUENTIAL,
+            #[cfg(target_os = "linux")]
+            Advice::HugePage => MmapAdvise::MADV_HUGEPAGE,
+            #[cfg(target_os = "linux")]
+            Advice::Remove => MmapAdvise::MADV_REMOVE,
+            #[cfg(target_os = "linux")]
+            Advice::DontFork => MmapAdvise::MADV_DONTFORK,
+        }
+    }
+
+    /// Returns whether the advice can discard the contents of the pages
+    fn is_destructive(self) -> bool {
+        match self {
+            Advice::DontNeed => true,
+            #[cfg(target_os = "linux")]
+            Advice::Remove => true,
+            _ => false,
+        }
+    }
+}
+
+/// Access allowed to a range of a mapping, see `Shmem::protect()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    /// Any access crashes the process with `SIGSEGV`
+    None,
+    /// Writes crash the process with `SIGSEGV`
+    Read,
+    /// Reads and writes are allowed, which is how mappings that are not `read_only()` start
+    ReadWrite,
+}
+
+impl Protection {
+    fn as_prot_flags(self) -> ProtFlags {
+        match self {
+            Protection::None => ProtFlags::PROT_NONE,
+            Protection::Read => ProtFlags::PROT_READ,
+            Protection::ReadWrite => ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+        }
+    }
+
+    /// Applies the protection to the pages covering `len` bytes from `addr`, which is page aligned
+    pub(crate) fn apply(self, addr: *mut u8, len: usize) -> nix::Result<()> {
+        trace!("mprotect({:p}, {}, {:?})", addr, len, self);
+        unsafe { mprotect(addr as *mut _, len, self.as_prot_flags()) }
+    }
+}
 
 #[derive(Clone, Default)]
-pub struct ShmemConfExt;
+pub struct ShmemConfExt {
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    memfd: bool,
+    file_backed: bool,
+    sysv: bool,
+    lock: bool,
+    populate: bool,
+    guard_pages: bool,
+    preallocate: bool,
+    #[cfg(target_os = "linux")]
+    numa: Option<NumaPolicy>,
+    #[cfg(target_os = "linux")]
+    huge_pages: Option<HugePageSize>,
+    #[cfg(target_os = "linux")]
+    huge_pages_fallback: bool,
+    pub(crate) faults: Faults,
+}
+
+/// Size of the huge pages backing a mapping, see `ShmemConf::huge_pages()`
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HugePageSize {
+    /// 2 MiB pages
+    Size2MiB,
+    /// 1 GiB pages
+    Size1GiB,
+}
+
+#[cfg(target_os = "linux")]
+impl HugePageSize {
+    /// Returns the size of a page in bytes
+    pub fn bytes(self) -> usize {
+        match self {
+            HugePageSize::Size2MiB => 2 << 20,
+            HugePageSize::Size1GiB => 1 << 30,
+        }
+    }
+
+    fn memfd_flag(self) -> libc::c_uint {
+        match self {
+            HugePageSize::Size2MiB => libc::MFD_HUGE_2MB,
+            HugePageSize::Size1GiB => libc::MFD_HUGE_1GB,
+        }
+    }
+}
+
+/// NUMA placement of the pages of a mapping, see `ShmemConf::numa_node()`
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug)]
+enum NumaPolicy {
+    Node(u32),
+    Interleave,
+}
+
+impl ShmemConf {
+    /// Sets the permission bits applied to the mapping in `create()`
+    ///
+    /// The bits are applied with `fchmod()` after the object is created so they are not affected by the process umask.
+    /// When not specified, only the creating user can read and write to the mapping (`0o600`)
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.ext.mode = Some(mode);
+        self
+    }
 
+    /// Sets the user that owns the mapping created in `create()`
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.ext.uid = Some(uid);
+        self
+    }
+
+    /// Sets the group that owns the mapping created in `create()`
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.ext.gid = Some(gid);
+        self
+    }
+}
+
+/// Backend for the OS shared memory objects, configured by the unix specific options of `ShmemConf`
+struct OsBackend {
+    ext: ShmemConfExt,
+}
+
+/// Returns the backend used when `ShmemConf::backend()` was not called
+pub fn default_backend(ext: &ShmemConfExt) -> Arc<dyn ShmemBackend> {
+    Arc::new(OsBackend { ext: ext.clone() })
+}
+
+impl ShmemBackend for OsBackend {
+    fn create(&self, os_id: &str, size: usize) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        create_mapping(os_id, size, &self.ext)
+    }
+
+    fn open(
+        &self,
+        os_id: &str,
+        _size: usize,
+        read_only: bool,
+    ) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        open_mapping(os_id, None, read_only, &self.ext)
+    }
+
+    fn open_window(
+        &self,
+        os_id: &str,
+        offset: u64,
+        size: usize,
+        read_only: bool,
+    ) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        open_mapping(os_id, Some((offset, size)), read_only, &self.ext)
+    }
+}
+
+impl ShmemConfExt {
+    fn is_huge(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return self.huge_pages.is_some();
+        #[cfg(not(target_os = "linux"))]
+        return false;
+    }
+
+    /// Returns whether `populate()` can be done while mapping, pages must not be touched before they are placed
+    fn populate_on_map(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return self.populate && self.numa.is_none();
+        #[cfg(not(target_os = "linux"))]
+        return self.populate;
+    }
+}
+
+/// An object mapped through its file descriptor
 pub struct MapData {
-    //On linux, you must shm_unlink() the object created for the mapping. It wont disappear automatically.
-    owner: bool,
+    //What kind of object is mapped, it decides how the object is removed
+    kind: ObjectKind,
+    //Whether the mapping was made without write access
+    read_only: bool,
+    //Whether the pages are locked into RAM
+    locked: bool,
+    //Whether the mapping is surrounded by guard pages
+    guarded: bool,
+    //Whether writes stay in our process, see private_view()
+    private: bool,
+    //Whether the storage of the object is allocated up front
+    preallocated: bool,
+    //Where the pages of the mapping are allocated
+    #[cfg(target_os = "linux")]
+    numa: Option<NumaPolicy>,
+    //Size of the huge pages backing the mapping, its size must stay a multiple of it
+    #[cfg(target_os = "linux")]
+    huge_pages: Option<HugePageSize>,
 
     //File descriptor to our open mapping
     map_fd: RawFd,
 
     //Shared mapping uid
-    pub unique_id: String,
+    unique_id: String,
     //Total size of the mapping
-    pub map_size: usize,
+    map_size: usize,
+    //Offset of the mapping in the object
+    map_offset: u64,
     //Pointer to the first address of our mapping
-    pub map_ptr: *mut u8,
+    map_ptr: *mut u8,
+    //Failures planned for the syscalls made on the mapping, see ShmemConf::fault_plan()
+    faults: Faults,
 }
 
-impl MapData {
-    pub fn as_mut_ptr(&self) -> *mut u8 {
-        self.map_ptr
-    }
+/// Kind of object mapped by a `MapData`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ObjectKind {
+    /// POSIX shared memory object, see `shm_open()`
+    Shm,
+    /// Regular file, see `ShmemConf::file_backed()`
+    File,
+    /// Object only shared through its file descriptor, see `ShmemConf::memfd()` and `ShmemConf::open_fd()`
+    Anonymous,
 }
 
 /// Shared memory teardown for linux
 impl Drop for MapData {
-    ///Takes care of properly closing the SharedMem (munmap(), shmem_unlink(), close())
+    ///Takes care of properly closing the SharedMem (munmap(), close())
     fn drop(&mut self) {
         //Unmap memory
         if !self.map_ptr.is_null() {
-            trace!(
-                "munmap(map_ptr:{:p},map_size:{})",
-                self.map_ptr,
-                self.map_size
-            );
-            if let Err(_e) = unsafe { munmap(self.map_ptr as *mut _, self.map_size) } {
+            if let Err(_e) = unsafe { unmap(self.map_ptr, self.map_size, self.guarded) } {
                 debug!("Failed to munmap() shared memory mapping : {}", _e);
             };
         }
 
-        //Unlink shmem
         if self.map_fd != 0 {
-            //unlink shmem if we created it
-            if self.owner {
-                debug!("Deleting persistent mapping");
-                trace!("shm_unlink({})", self.unique_id.as_str());
-                if let Err(_e) = shm_unlink(self.unique_id.as_str()) {
-                    debug!("Failed to shm_unlink() shared memory : {}", _e);
-                };
-            }
-
             trace!("close({})", self.map_fd);
             if let Err(_e) = close(self.map_fd) {
                 debug!(
@@ -72,26 +312,654 @@ impl Drop for MapData {
     }
 }
 
+unsafe impl ShmemMapping for MapData {
+    fn os_id(&self) -> &str {
+        self.unique_id.as_str()
+    }
+
+    fn len(&self) -> usize {
+        self.map_size
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        self.map_ptr
+    }
+
+    fn object_size(&self) -> Result<usize, ShmemError> {
+        match object_size(self.map_fd) {
+            Ok(v) => usize::try_from(v).map_err(|_| self.os_error("fstat", nix::Error::EOVERFLOW)),
+            Err(e) => Err(self.os_error("fstat", e)),
+        }
+    }
+
+    fn resize(&mut self, new_size: usize) -> Result<(), ShmemError> {
+        if self.private {
+            return Err(ShmemError::Unsupported("Resizing private views"));
+        }
+        // hugetlbfs only accepts sizes that are a multiple of the page size
+        #[cfg(target_os = "linux")]
+        let new_size = match self.huge_pages {
+            Some(page_size) => new_size.div_ceil(page_size.bytes()) * page_size.bytes(),
+            None => new_size,
+        };
+        let (fd, old_size) = (self.map_fd, self.map_size);
+        trace!("ftruncate({}, {})", fd, new_size);
+        if let Err(e) =
+            injected(&self.faults, "ftruncate").and_then(|_| ftruncate(fd, new_size as _))
+        {
+            return Err(self.os_error("ftruncate", e));
+        }
+        self.remap(new_size)?;
+        // remap() placed the new pages, their storage can now be allocated
+        if self.preallocated && new_size > old_size {
+            if let Err(e) = preallocate(
+                fd,
+                old_size,
+                new_size - old_size,
+                &self.unique_id,
+                &self.faults,
+                mapping_error,
+            ) {
+                // Go back to the size we have storage for
+                let _ = ftruncate(fd, old_size as _);
+                let _ = self.remap(old_size);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn remap(&mut self, new_size: usize) -> Result<(), ShmemError> {
+        if new_size == self.map_size {
+            return Ok(());
+        }
+        // Mapping the object again would drop our private copies of the pages
+        if self.private {
+            return Err(ShmemError::Unsupported("Resizing private views"));
+        }
+        let nz_map_size = NonZeroUsize::new(new_size).ok_or(ShmemError::MapSizeZero)?;
+
+        #[cfg(target_os = "linux")]
+        let res = if self.guarded {
+            // Growing the mapping in place would run into the guard page
+            self.map_again(nz_map_size)
+        } else {
+            match unsafe {
+                mremap(
+                    self.map_ptr as *mut _,
+                    self.map_size,
+                    nz_map_size.get(),
+                    MRemapFlags::MREMAP_MAYMOVE,
+                    None,
+                )
+            } {
+                Ok(new_ptr) => Ok(new_ptr as *mut u8),
+                // mremap() cannot move a range that Shmem::protect() split into several areas
+                Err(nix::Error::EFAULT) => self.map_again(nz_map_size),
+                Err(e) => Err(self.os_error("mremap", e)),
+            }
+        };
+        #[cfg(not(target_os = "linux"))]
+        let res = self.map_again(nz_map_size);
+
+        let new_ptr = res?;
+        trace!(
+            "remap({:p}, {}, {}) == {:p}",
+            self.map_ptr,
+            self.map_size,
+            new_size,
+            new_ptr
+        );
+        self.map_ptr = new_ptr;
+        self.map_size = new_size;
+        // Pages past the previous size have no placement yet
+        #[cfg(target_os = "linux")]
+        if let Some(policy) = self.numa {
+            if let Err(e) = bind_pages(self.map_ptr, self.map_size, policy) {
+                return Err(self.os_error("mbind", e));
+            }
+        }
+        Ok(())
+    }
+
+    fn remap_at(&mut self, addr: usize) -> Result<(), ShmemError> {
+        if addr == self.map_ptr as usize {
+            return Ok(());
+        }
+        let nz_map_size = NonZeroUsize::new(self.map_size).ok_or(ShmemError::MapSizeZero)?;
+        let nz_addr = NonZeroUsize::new(addr).ok_or(ShmemError::AddressUnavailable { addr })?;
+        if self.private {
+            return Err(ShmemError::Unsupported("Moving private views"));
+        }
+        let prot = prot_flags(self.read_only);
+
+        // Our current mapping might overlap the requested range, release it first
+        if let Err(e) = unsafe { unmap(self.map_ptr, self.map_size, self.guarded) } {
+            return Err(self.os_error("munmap", e));
+        }
+        self.map_ptr = null_mut();
+
+        let (fd, offset, guarded) = (self.map_fd, self.map_offset, self.guarded);
+        let res = match map_fd_at(fd, nz_map_size, prot, nz_addr, offset, guarded) {
+            Ok(v) => {
+                self.map_ptr = v;
+                Ok(())
+            }
+            Err(nix::Error::EEXIST) => Err(ShmemError::AddressUnavailable { addr }),
+            Err(e) => Err(self.os_error("mmap", e)),
+        };
+        if res.is_err() {
+            // Map the object back anywhere so it stays usable
+            self.map_ptr = map_fd(fd, nz_map_size, prot, false, offset, guarded)
+                .map_err(|e| self.os_error("mmap", e))?;
+        }
+        if self.locked {
+            lock_pages(
+                self.map_ptr,
+                self.map_size,
+                &self.unique_id,
+                &self.faults,
+                mapping_error,
+            )?;
+        }
+        res
+    }
+
+    fn unlink(&self) -> Result<(), ShmemError> {
+        let os_id = self.unique_id.as_str();
+        match self.kind {
+            ObjectKind::Shm => {
+                debug!("Deleting persistent mapping");
+                trace!("shm_unlink({})", os_id);
+                shm_unlink(os_id).map_err(|e| self.os_error("shm_unlink", e))
+            }
+            ObjectKind::File => {
+                debug!("Deleting backing file");
+                trace!("unlink({})", os_id);
+                unlink(os_id).map_err(|e| self.os_error("unlink", e))
+            }
+            // Anonymous objects disappear with their last file descriptor
+            ObjectKind::Anonymous => Ok(()),
+        }
+    }
+
+    fn private_view(&self) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        let nz_map_size = NonZeroUsize::new(self.map_size).ok_or(ShmemError::MapSizeZero)?;
+        // The view closes its own descriptor when it is dropped
+        let fd = match fcntl(self.map_fd, FcntlArg::F_DUPFD_CLOEXEC(0)) {
+            Ok(v) => v,
+            Err(e) => return Err(self.os_error("fcntl", e)),
+        };
+        let mut view = MapData {
+            kind: self.kind,
+            read_only: false,
+            locked: false,
+            guarded: self.guarded,
+            private: true,
+            preallocated: false,
+            #[cfg(target_os = "linux")]
+            numa: None,
+            #[cfg(target_os = "linux")]
+            huge_pages: self.huge_pages,
+            map_offset: self.map_offset,
+            unique_id: self.unique_id.clone(),
+            map_fd: fd,
+            map_size: self.map_size,
+            map_ptr: null_mut(),
+            faults: self.faults.clone(),
+        };
+
+        // Private mappings can be written to even when the descriptor is read-only
+        let prot = prot_flags(false);
+        let flags = MapFlags::MAP_PRIVATE;
+        let res = if self.guarded {
+            reserve_guarded(nz_map_size, None).and_then(|addr| {
+                map_in_reservation(fd, nz_map_size, prot, flags, addr, self.map_offset)
+            })
+        } else {
+            unsafe { mmap_fd(None, nz_map_size, prot, flags, fd, self.map_offset) }.map(|map_ptr| {
+                trace!(
+                    "mmap(NULL, {}, {:X}, {:X}, {}, {}) == {:p}",
+                    nz_map_size,
+                    prot,
+                    flags,
+                    fd,
+                    self.map_offset,
+                    map_ptr
+                );
+                map_ptr as *mut u8
+            })
+        };
+        view.map_ptr = res.map_err(|e| self.os_error("mmap", e))?;
+        Ok(Box::new(view))
+    }
+
+    fn protect(&self, range: Range<usize>, protection: Protection) -> Result<(), ShmemError> {
+        let addr = unsafe { self.map_ptr.add(range.start) };
+        protection
+            .apply(addr, range.len())
+            .map_err(|e| self.os_error("mprotect", e))
+    }
+
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        Some(self.map_fd)
+    }
+}
+
 impl MapData {
-    pub fn set_owner(&mut self, is_owner: bool) -> bool {
-        let prev_val = self.owner;
-        self.owner = is_owner;
-        prev_val
+    /// Maps the object again with a new size and releases the previous mapping
+    fn map_again(&self, map_size: NonZeroUsize) -> Result<*mut u8, ShmemError> {
+        let prot = prot_flags(self.read_only);
+        let new_ptr = map_fd(
+            self.map_fd,
+            map_size,
+            prot,
+            false,
+            self.map_offset,
+            self.guarded,
+        )
+        .map_err(|e| self.os_error("mmap", e))?;
+        // mremap() keeps the pages locked but a new mapping has to be locked again
+        if self.locked {
+            let (unique_id, faults) = (&self.unique_id, &self.faults);
+            if let Err(e) = lock_pages(new_ptr, map_size.get(), unique_id, faults, mapping_error) {
+                let _ = unsafe { unmap(new_ptr, map_size.get(), self.guarded) };
+                return Err(e);
+            }
+        }
+        let _ = unsafe { unmap(self.map_ptr, self.map_size, self.guarded) };
+        Ok(new_ptr)
+    }
+
+    fn os_error(&self, syscall: &'static str, e: nix::Error) -> ShmemError {
+        ShmemError::OsError {
+            syscall,
+            os_id: self.unique_id.clone(),
+            source: e.into(),
+        }
+    }
+}
+
+/// Returns the protection flags of a mapping
+fn prot_flags(read_only: bool) -> ProtFlags {
+    if read_only {
+        ProtFlags::PROT_READ
+    } else {
+        ProtFlags::PROT_READ | ProtFlags::PROT_WRITE
+    }
+}
+
+/// Fails with the error planned for `syscall` by `ShmemConf::fault_plan()`, if any
+///
+/// Called by the OS backend before the syscalls that create, open or resize a mapping
+fn injected(faults: &Faults, syscall: &str) -> nix::Result<()> {
+    faults
+        .check(syscall)
+        .map_err(|e| nix::Error::from_i32(e.raw_os_error().unwrap_or(libc::EIO)))
+}
+
+/// Wraps an error that occured while creating the mapping
+fn create_error(syscall: &'static str, unique_id: &str, e: nix::Error) -> ShmemError {
+    ShmemError::MapCreateFailed {
+        syscall,
+        os_id: unique_id.to_string(),
+        source: e.into(),
+    }
+}
+
+/// Wraps an error that occured while using an existing mapping
+fn mapping_error(syscall: &'static str, unique_id: &str, e: nix::Error) -> ShmemError {
+    ShmemError::OsError {
+        syscall,
+        os_id: unique_id.to_string(),
+        source: e.into(),
+    }
+}
+
+/// Wraps an error that occured while opening the mapping
+fn open_error(syscall: &'static str, unique_id: &str, e: nix::Error) -> ShmemError {
+    ShmemError::MapOpenFailed {
+        syscall,
+        os_id: unique_id.to_string(),
+        source: e.into(),
+    }
+}
+
+/// Classifies OS errors that are not covered by `std::io::ErrorKind`
+pub fn error_kind(err: &io::Error) -> ShmemErrorKind {
+    match err.raw_os_error() {
+        Some(libc::ENOSPC) | Some(libc::EDQUOT) => ShmemErrorKind::NoSpace,
+        Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOMEM) | Some(libc::EAGAIN) => {
+            ShmemErrorKind::ResourceLimit
+        }
+        Some(libc::ENAMETOOLONG) => ShmemErrorKind::InvalidInput,
+        _ => ShmemErrorKind::Other,
     }
 }
 
 /// Creates a mapping specified by the uid and size
-pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, ShmemError> {
-    //Create shared memory file descriptor
-    debug!("Creating persistent mapping at {}", unique_id);
+fn create_mapping(
+    unique_id: &str,
+    map_size: usize,
+    ext: &ShmemConfExt,
+) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+    if map_size == 0 {
+        return Err(ShmemError::MapSizeZero);
+    }
+    if ext.guard_pages && ext.sysv {
+        return Err(ShmemError::Unsupported(
+            "Guard pages around System V segments",
+        ));
+    }
+    if ext.guard_pages && ext.is_huge() {
+        return Err(ShmemError::Unsupported("Guard pages around huge pages"));
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(page_size) = ext.huge_pages {
+        match create_huge(unique_id, map_size, page_size, ext) {
+            Err(ShmemError::HugePagesUnavailable(_e)) if ext.huge_pages_fallback => {
+                debug!("Falling back to regular pages : {}", _e);
+            }
+            res => return Ok(Box::new(res?)),
+        }
+    }
+
+    if ext.sysv {
+        return Ok(Box::new(create_sysv(unique_id, map_size, ext)?));
+    }
+    if ext.file_backed {
+        let new_map = create_file(unique_id, map_size, ext)?;
+        let new_map = discard_on_error(setup_mapping(new_map, ext), || {
+            trace!("unlink({})", unique_id);
+            unlink(unique_id)
+        })?;
+        return Ok(Box::new(new_map));
+    }
+
+    #[cfg(target_os = "linux")]
+    if ext.memfd || ext.huge_pages.is_some() {
+        let new_map = create_memfd(unique_id, map_size, None, ext)?;
+        return Ok(Box::new(setup_mapping(new_map, ext)?));
+    }
+
+    let new_map = create_shm(unique_id, map_size, ext)?;
+    let new_map = discard_on_error(setup_mapping(new_map, ext), || {
+        trace!("shm_unlink({})", unique_id);
+        shm_unlink(unique_id)
+    })?;
+    Ok(Box::new(new_map))
+}
+
+/// Creates a memfd backed by huge pages, its size is rounded up to a multiple of the page size
+#[cfg(target_os = "linux")]
+fn create_huge(
+    unique_id: &str,
+    map_size: usize,
+    page_size: HugePageSize,
+    ext: &ShmemConfExt,
+) -> Result<MapData, ShmemError> {
+    let page_bytes = page_size.bytes();
+    let map_size = map_size.div_ceil(page_bytes) * page_bytes;
+
+    match create_memfd(unique_id, map_size, Some(page_size), ext)
+        .and_then(|m| setup_mapping(m, ext))
+    {
+        // memfd_create() rejects page sizes the kernel does not support and mmap() fails when
+        // the pool of huge pages cannot cover the mapping
+        Err(ShmemError::MapCreateFailed {
+            syscall: "memfd_create" | "mmap",
+            source,
+            ..
+        }) if matches!(
+            source.raw_os_error(),
+            Some(libc::EINVAL) | Some(libc::ENOMEM) | Some(libc::ENOENT)
+        ) =>
+        {
+            Err(ShmemError::HugePagesUnavailable(source))
+        }
+        res => res,
+    }
+}
+
+/// Applies the configuration to a freshly created object and maps it into our address space
+fn setup_mapping(mut new_map: MapData, ext: &ShmemConfExt) -> Result<MapData, ShmemError> {
+    let nz_map_size = NonZeroUsize::new(new_map.map_size).ok_or(ShmemError::MapSizeZero)?;
+    let unique_id = new_map.unique_id.as_str();
+
+    //Apply ownership before the permissions as fchown() may clear some mode bits
+    if ext.uid.is_some() || ext.gid.is_some() {
+        let uid = ext.uid.map(Uid::from_raw);
+        let gid = ext.gid.map(Gid::from_raw);
+        trace!("fchown({}, {:?}, {:?})", new_map.map_fd, uid, gid);
+        if let Err(e) =
+            injected(&ext.faults, "fchown").and_then(|_| fchown(new_map.map_fd, uid, gid))
+        {
+            return Err(create_error("fchown", unique_id, e));
+        }
+    }
+
+    //Set the requested permissions, fchmod() ignores the umask that was applied by shm_open()
+    if let Some(mode) = ext.mode {
+        let mode = Mode::from_bits_truncate(mode as _);
+        trace!("fchmod({}, {:X})", new_map.map_fd, mode);
+        if let Err(e) = injected(&ext.faults, "fchmod").and_then(|_| fchmod(new_map.map_fd, mode)) {
+            return Err(create_error("fchmod", unique_id, e));
+        }
+    }
+
+    //Enlarge the memory descriptor file size to the requested map size
+    debug!("Creating memory mapping");
+    trace!("ftruncate({}, {})", new_map.map_fd, new_map.map_size);
+    match injected(&ext.faults, "ftruncate")
+        .and_then(|_| ftruncate(new_map.map_fd, new_map.map_size as _))
+    {
+        Ok(_) => {}
+        Err(e) => return Err(create_error("ftruncate", unique_id, e)),
+    };
+    //Put the mapping in our address space
+    debug!("Loading mapping into address space");
+    new_map.map_ptr = match injected(&ext.faults, "mmap").and_then(|_| {
+        map_fd(
+            new_map.map_fd,
+            nz_map_size,
+            prot_flags(false),
+            ext.populate_on_map(),
+            0,
+            ext.guard_pages,
+        )
+    }) {
+        Ok(v) => v,
+        Err(e) => return Err(create_error("mmap", unique_id, e)),
+    };
+    new_map.guarded = ext.guard_pages;
+    #[cfg(target_os = "linux")]
+    if let Some(policy) = ext.numa {
+        place_pages(
+            new_map.map_ptr,
+            new_map.map_size,
+            policy,
+            unique_id,
+            ext,
+            create_error,
+        )?;
+        new_map.numa = Some(policy);
+    }
+    // The storage is allocated according to the NUMA policy of the pages
+    if ext.preallocate {
+        let (fd, map_size) = (new_map.map_fd, new_map.map_size);
+        preallocate(fd, 0, map_size, unique_id, &ext.faults, create_error)?;
+        new_map.preallocated = true;
+    }
+    if ext.lock {
+        let (map_ptr, map_size) = (new_map.map_ptr, new_map.map_size);
+        lock_pages(map_ptr, map_size, unique_id, &ext.faults, create_error)?;
+        new_map.locked = true;
+    }
+
+    Ok(new_map)
+}
+
+/// Parses the key of a System V segment from the os_id of the mapping
+fn sysv_key(unique_id: &str) -> Result<libc::key_t, io::Error> {
+    sysv::parse_key(unique_id).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the os_id is not a System V key",
+        )
+    })
+}
+
+/// Creates and attaches a System V segment
+fn create_sysv(
+    unique_id: &str,
+    map_size: usize,
+    ext: &ShmemConfExt,
+) -> Result<SysvMapping, ShmemError> {
+    let key = sysv_key(unique_id).map_err(|e| ShmemError::MapCreateFailed {
+        syscall: "shmget",
+        os_id: unique_id.to_string(),
+        source: e,
+    })?;
 
-    let nz_map_size = NonZeroUsize::new(map_size).ok_or(ShmemError::MapSizeZero)?;
+    debug!("Creating System V segment {}", unique_id);
+    let mode = ext.mode.unwrap_or(0o600);
+    let shmid =
+        match injected(&ext.faults, "shmget").and_then(|_| sysv::create(key, map_size, mode)) {
+            Ok(v) => v,
+            Err(nix::Error::EEXIST) => return Err(ShmemError::MappingIdExists),
+            Err(e) => return Err(create_error("shmget", unique_id, e)),
+        };
+    let new_map = SysvMapping {
+        shmid,
+        unique_id: String::from(unique_id),
+        map_size,
+        map_ptr: null_mut(),
+    };
+    discard_on_error(setup_sysv(new_map, ext), || sysv::remove(shmid))
+}
+
+/// Sets the owner of a new System V segment and attaches it
+fn setup_sysv(mut new_map: SysvMapping, ext: &ShmemConfExt) -> Result<SysvMapping, ShmemError> {
+    let (shmid, unique_id) = (new_map.shmid, new_map.unique_id.as_str());
+    if ext.uid.is_some() || ext.gid.is_some() {
+        if let Err(e) =
+            injected(&ext.faults, "shmctl").and_then(|_| sysv::chown(shmid, ext.uid, ext.gid))
+        {
+            return Err(create_error("shmctl", unique_id, e));
+        }
+    }
+
+    debug!("Loading mapping into address space");
+    new_map.map_ptr = match injected(&ext.faults, "shmat").and_then(|_| sysv::attach(shmid, false))
+    {
+        Ok(v) => v,
+        Err(e) => return Err(create_error("shmat", unique_id, e)),
+    };
+    prepare_pages(
+        new_map.map_ptr,
+        new_map.map_size,
+        unique_id,
+        ext,
+        create_error,
+    )?;
+    Ok(new_map)
+}
+
+/// Attaches an existing System V segment, its size is queried with shmctl(IPC_STAT)
+fn open_sysv(
+    unique_id: &str,
+    read_only: bool,
+    ext: &ShmemConfExt,
+) -> Result<SysvMapping, ShmemError> {
+    let key = sysv_key(unique_id).map_err(|e| ShmemError::MapOpenFailed {
+        syscall: "shmget",
+        os_id: unique_id.to_string(),
+        source: e,
+    })?;
 
-    let shmem_fd = match shm_open(
-        unique_id, //Unique name that usualy pops up in /dev/shm/
-        OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_RDWR, //create exclusively (error if collision) and read/write to allow resize
-        Mode::S_IRUSR | Mode::S_IWUSR,                  //Permission allow user+rw
-    ) {
+    debug!("Openning System V segment {}", unique_id);
+    let shmid = match injected(&ext.faults, "shmget").and_then(|_| sysv::open(key)) {
+        Ok(v) => v,
+        Err(e) => return Err(open_error("shmget", unique_id, e)),
+    };
+    let mut new_map = SysvMapping {
+        shmid,
+        unique_id: String::from(unique_id),
+        map_size: 0,
+        map_ptr: null_mut(),
+    };
+
+    new_map.map_size = match injected(&ext.faults, "shmctl").and_then(|_| sysv::stat(shmid)) {
+        Ok(ds) => ds.shm_segsz,
+        Err(e) => return Err(open_error("shmctl", unique_id, e)),
+    };
+    if new_map.map_size == 0 {
+        return Err(ShmemError::MapSizeZero);
+    }
+
+    debug!("Loading mapping into address space");
+    new_map.map_ptr =
+        match injected(&ext.faults, "shmat").and_then(|_| sysv::attach(shmid, read_only)) {
+            Ok(v) => v,
+            Err(e) => return Err(open_error("shmat", unique_id, e)),
+        };
+    prepare_pages(
+        new_map.map_ptr,
+        new_map.map_size,
+        unique_id,
+        ext,
+        open_error,
+    )?;
+    Ok(new_map)
+}
+
+/// Creates the regular file that backs a mapping
+fn create_file(path: &str, map_size: usize, ext: &ShmemConfExt) -> Result<MapData, ShmemError> {
+    let oflag = OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_RDWR | OFlag::O_CLOEXEC;
+    let mode = Mode::S_IRUSR | Mode::S_IWUSR;
+
+    debug!("Creating backing file {}", path);
+    let fd = match injected(&ext.faults, "open").and_then(|_| open(path, oflag, mode)) {
+        Ok(v) => {
+            trace!("open({}, {:X}, {:X}) == {}", path, oflag, mode, v);
+            v
+        }
+        Err(nix::Error::EEXIST) => return Err(ShmemError::MappingIdExists),
+        Err(e) => return Err(create_error("open", path, e)),
+    };
+
+    Ok(MapData {
+        kind: ObjectKind::File,
+        read_only: false,
+        locked: false,
+        guarded: false,
+        private: false,
+        preallocated: false,
+        #[cfg(target_os = "linux")]
+        numa: None,
+        #[cfg(target_os = "linux")]
+        huge_pages: None,
+        map_offset: 0,
+        unique_id: String::from(path),
+        map_fd: fd,
+        map_size,
+        map_ptr: null_mut(),
+        faults: ext.faults.clone(),
+    })
+}
+
+/// Creates a named POSIX shared memory object
+fn create_shm(unique_id: &str, map_size: usize, ext: &ShmemConfExt) -> Result<MapData, ShmemError> {
+    //Create shared memory file descriptor
+    debug!("Creating persistent mapping at {}", unique_id);
+    let shmem_fd = match injected(&ext.faults, "shm_open").and_then(|_| {
+        shm_open(
+            unique_id, //Unique name that usualy pops up in /dev/shm/
+            OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_RDWR, //create exclusively (error if collision) and read/write to allow resize
+            Mode::S_IRUSR | Mode::S_IWUSR,                  //Permission allow user+rw
+        )
+    }) {
         Ok(v) => {
             trace!(
                 "shm_open({}, {:X}, {:X}) == {}",
@@ -103,121 +971,1443 @@ pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, Shmem
             v
         }
         Err(nix::Error::EEXIST) => return Err(ShmemError::MappingIdExists),
-        Err(e) => return Err(ShmemError::MapCreateFailed(e as u32)),
+        Err(e) => return Err(create_error("shm_open", unique_id, e)),
     };
 
-    let mut new_map: MapData = MapData {
-        owner: true,
+    Ok(MapData {
+        kind: ObjectKind::Shm,
+        read_only: false,
+        locked: false,
+        guarded: false,
+        private: false,
+        preallocated: false,
+        #[cfg(target_os = "linux")]
+        numa: None,
+        #[cfg(target_os = "linux")]
+        huge_pages: None,
+        map_offset: 0,
         unique_id: String::from(unique_id),
         map_fd: shmem_fd,
         map_size,
         map_ptr: null_mut(),
-    };
-
-    //Enlarge the memory descriptor file size to the requested map size
-    debug!("Creating memory mapping");
-    trace!("ftruncate({}, {})", new_map.map_fd, new_map.map_size);
-    match ftruncate(new_map.map_fd, new_map.map_size as _) {
-        Ok(_) => {}
-        Err(e) => return Err(ShmemError::UnknownOsError(e as u32)),
-    };
+        faults: ext.faults.clone(),
+    })
+}
 
-    //Put the mapping in our address space
-    debug!("Loading mapping into address space");
-    new_map.map_ptr = match unsafe {
-        mmap(
-            None,                                         //Desired addr
-            nz_map_size,                                  //size of mapping
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE, //Permissions on pages
-            MapFlags::MAP_SHARED,                         //What kind of mapping
-            new_map.map_fd,                               //fd
-            0,                                            //Offset into fd
-        )
-    } {
-        Ok(v) => {
-            trace!(
-                "mmap(NULL, {}, {:X}, {:X}, {}, 0) == {:p}",
-                new_map.map_size,
-                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-                MapFlags::MAP_SHARED,
-                new_map.map_fd,
+/// Creates an anonymous memory file that can only be shared through its file descriptor
+#[cfg(target_os = "linux")]
+fn create_memfd(
+    unique_id: &str,
+    map_size: usize,
+    huge_pages: Option<HugePageSize>,
+    ext: &ShmemConfExt,
+) -> Result<MapData, ShmemError> {
+    // The name is only used for debugging purposes, it shows up in /proc/self/fd/
+    let name = unique_id.trim_start_matches('/');
+    debug!("Creating anonymous memfd mapping {}", name);
+    let c_name = CString::new(name)
+        .map_err(|_| create_error("memfd_create", unique_id, nix::Error::EINVAL))?;
+    // Allow sealing so the creator can later freeze the contents with Shmem::seal()
+    let mut flags = MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING;
+    if let Some(page_size) = huge_pages {
+        // nix does not know about the hugetlb flags
+        flags |= unsafe {
+            MemFdCreateFlag::from_bits_unchecked(libc::MFD_HUGETLB | page_size.memfd_flag())
+        };
+    }
+    let memfd =
+        match injected(&ext.faults, "memfd_create").and_then(|_| memfd_create(&c_name, flags)) {
+            Ok(v) => {
+                trace!("memfd_create({}, {:X}) == {}", name, flags, v);
                 v
-            );
-            v as *mut _
-        }
-        Err(e) => return Err(ShmemError::MapCreateFailed(e as u32)),
-    };
+            }
+            Err(e) => return Err(create_error("memfd_create", unique_id, e)),
+        };
 
-    Ok(new_map)
+    Ok(MapData {
+        kind: ObjectKind::Anonymous,
+        read_only: false,
+        locked: false,
+        guarded: false,
+        private: false,
+        preallocated: false,
+        #[cfg(target_os = "linux")]
+        numa: None,
+        #[cfg(target_os = "linux")]
+        huge_pages,
+        map_offset: 0,
+        unique_id: format!("memfd:{name}"),
+        map_fd: memfd,
+        map_size,
+        map_ptr: null_mut(),
+        faults: ext.faults.clone(),
+    })
 }
 
 /// Opens an existing mapping specified by its uid
-pub fn open_mapping(
+///
+/// Only the `(offset, size)` window of the object is mapped when one is provided
+fn open_mapping(
     unique_id: &str,
-    _map_size: usize,
-    _ext: &ShmemConfExt,
-) -> Result<MapData, ShmemError> {
+    window: Option<(u64, usize)>,
+    read_only: bool,
+    ext: &ShmemConfExt,
+) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+    if ext.sysv {
+        if window.is_some() {
+            return Err(ShmemError::Unsupported(
+                "Opening part of a System V segment",
+            ));
+        }
+        if ext.guard_pages {
+            return Err(ShmemError::Unsupported(
+                "Guard pages around System V segments",
+            ));
+        }
+        return Ok(Box::new(open_sysv(unique_id, read_only, ext)?));
+    }
+    if ext.file_backed {
+        return Ok(Box::new(open_file(unique_id, window, read_only, ext)?));
+    }
+    if ext.memfd {
+        return Err(ShmemError::Unsupported("Opening memfd mappings by id"));
+    }
+
+    let oflag = if read_only {
+        OFlag::O_RDONLY
+    } else {
+        OFlag::O_RDWR
+    };
+
     //Open shared memory
     debug!("Openning persistent mapping at {}", unique_id);
-    let shmem_fd = match shm_open(
-        unique_id,
-        OFlag::O_RDWR, //Open read write
-        Mode::S_IRUSR,
-    ) {
+    let shmem_fd = match injected(&ext.faults, "shm_open")
+        .and_then(|_| shm_open(unique_id, oflag, Mode::S_IRUSR))
+    {
         Ok(v) => {
             trace!(
                 "shm_open({}, {:X}, {:X}) == {}",
                 unique_id,
-                OFlag::O_RDWR,
+                oflag,
                 Mode::S_IRUSR,
                 v
             );
             v
         }
-        Err(e) => return Err(ShmemError::MapOpenFailed(e as u32)),
+        Err(e) => return Err(open_error("shm_open", unique_id, e)),
     };
 
+    Ok(Box::new(map_existing(
+        shmem_fd,
+        ObjectKind::Shm,
+        String::from(unique_id),
+        window,
+        read_only,
+        ext,
+    )?))
+}
+
+/// Opens the regular file backing a mapping
+fn open_file(
+    path: &str,
+    window: Option<(u64, usize)>,
+    read_only: bool,
+    ext: &ShmemConfExt,
+) -> Result<MapData, ShmemError> {
+    let oflag = if read_only {
+        OFlag::O_RDONLY
+    } else {
+        OFlag::O_RDWR
+    } | OFlag::O_CLOEXEC;
+
+    debug!("Openning backing file {}", path);
+    let fd = match injected(&ext.faults, "open").and_then(|_| open(path, oflag, Mode::empty())) {
+        Ok(v) => {
+            trace!("open({}, {:X}) == {}", path, oflag, v);
+            v
+        }
+        Err(e) => return Err(open_error("open", path, e)),
+    };
+
+    map_existing(
+        fd,
+        ObjectKind::File,
+        String::from(path),
+        window,
+        read_only,
+        ext,
+    )
+}
+
+/// Maps an open file descriptor of an existing object into our address space
+///
+/// The whole object is mapped unless a `(offset, size)` window is provided, a size of 0 spans up to the end of the object
+fn map_existing(
+    fd: RawFd,
+    kind: ObjectKind,
+    unique_id: String,
+    window: Option<(u64, usize)>,
+    read_only: bool,
+    ext: &ShmemConfExt,
+) -> Result<MapData, ShmemError> {
     let mut new_map: MapData = MapData {
-        owner: false,
-        unique_id: String::from(unique_id),
-        map_fd: shmem_fd,
+        kind,
+        read_only,
+        locked: false,
+        guarded: false,
+        private: false,
+        preallocated: false,
+        #[cfg(target_os = "linux")]
+        numa: None,
+        #[cfg(target_os = "linux")]
+        huge_pages: None,
+        map_offset: 0,
+        unique_id,
+        map_fd: fd,
         map_size: 0,
         map_ptr: null_mut(),
+        faults: ext.faults.clone(),
     };
+    let unique_id = new_map.unique_id.as_str();
 
     //Get mmap size
-    new_map.map_size = match fstat(new_map.map_fd) {
-        Ok(v) => v.st_size as usize,
-        Err(e) => return Err(ShmemError::MapOpenFailed(e as u32)),
+    let object_size = match injected(&ext.faults, "fstat").and_then(|_| object_size(new_map.map_fd))
+    {
+        Ok(v) => v,
+        Err(e) => return Err(open_error("fstat", unique_id, e)),
     };
+    let (offset, size) = mapped_range(unique_id, object_size, window)?;
+    new_map.map_offset = offset;
+    new_map.map_size = size;
 
     let nz_map_size = NonZeroUsize::new(new_map.map_size).ok_or(ShmemError::MapSizeZero)?;
 
     //Map memory into our address space
     debug!("Loading mapping into address space");
-    new_map.map_ptr = match unsafe {
-        mmap(
-            None,                                         //Desired addr
-            nz_map_size,                                  //size of mapping
-            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE, //Permissions on pages
-            MapFlags::MAP_SHARED,                         //What kind of mapping
-            new_map.map_fd,                               //fd
-            0,                                            //Offset into fd
-        )
-    } {
-        Ok(v) => {
+    let prot = prot_flags(read_only);
+    let (offset, guard) = (new_map.map_offset, ext.guard_pages);
+    let fd = new_map.map_fd;
+    new_map.map_ptr = match injected(&ext.faults, "mmap")
+        .and_then(|_| map_fd(fd, nz_map_size, prot, ext.populate_on_map(), offset, guard))
+    {
+        Ok(v) => v,
+        Err(e) => return Err(open_error("mmap", unique_id, e)),
+    };
+    new_map.guarded = guard;
+    #[cfg(target_os = "linux")]
+    if let Some(policy) = ext.numa {
+        place_pages(
+            new_map.map_ptr,
+            new_map.map_size,
+            policy,
+            unique_id,
+            ext,
+            open_error,
+        )?;
+        new_map.numa = Some(policy);
+    }
+    if ext.lock {
+        let (map_ptr, map_size) = (new_map.map_ptr, new_map.map_size);
+        lock_pages(map_ptr, map_size, unique_id, &ext.faults, open_error)?;
+        new_map.locked = true;
+    }
+
+    Ok(new_map)
+}
+
+/// Returns the offset and size of the part of an object of `object_size` bytes that `map_existing()` maps
+fn mapped_range(
+    unique_id: &str,
+    object_size: u64,
+    window: Option<(u64, usize)>,
+) -> Result<(u64, usize), ShmemError> {
+    let (offset, size) = window.unwrap_or((0, 0));
+    if offset % page_size() as u64 != 0 {
+        return Err(ShmemError::UnalignedOffset {
+            offset,
+            align: page_size(),
+        });
+    }
+    let size = match size {
+        // The rest of the object might not fit in the address space of 32 bit platforms
+        0 => usize::try_from(object_size.saturating_sub(offset))
+            .map_err(|_| open_error("fstat", unique_id, nix::Error::EOVERFLOW))?,
+        size => size,
+    };
+    let required = offset.saturating_add(size as u64);
+    if object_size < required {
+        return Err(ShmemError::MapTooSmall {
+            required: usize::try_from(required).unwrap_or(usize::MAX),
+            actual: usize::try_from(object_size).unwrap_or(usize::MAX),
+        });
+    }
+    Ok((offset, size))
+}
+
+/// Maps `map_size` bytes of the object behind `fd` from `offset` into our address space,
+/// faulting all of its pages in when `populate` is set and surrounding them with guard pages when `guard` is set
+fn map_fd(
+    fd: RawFd,
+    map_size: NonZeroUsize,
+    prot: ProtFlags,
+    populate: bool,
+    offset: u64,
+    guard: bool,
+) -> nix::Result<*mut u8> {
+    #[allow(unused_mut)]
+    let mut flags = MapFlags::MAP_SHARED;
+    #[cfg(target_os = "linux")]
+    if populate {
+        flags |= MapFlags::MAP_POPULATE;
+    }
+    let map_ptr = if guard {
+        let addr = reserve_guarded(map_size, None)?;
+        map_in_reservation(fd, map_size, prot, flags, addr, offset)?
+    } else {
+        let map_ptr = unsafe {
+            mmap_fd(
+                None,     //Desired addr
+                map_size, //size of mapping
+                prot,     //Permissions on pages
+                flags,    //What kind of mapping
+                fd,       //fd
+                offset,   //Offset into fd
+            )?
+        };
+        trace!(
+            "mmap(NULL, {}, {:X}, {:X}, {}, {}) == {:p}",
+            map_size,
+            prot,
+            flags,
+            fd,
+            offset,
+            map_ptr
+        );
+        map_ptr as *mut u8
+    };
+    #[cfg(not(target_os = "linux"))]
+    if populate {
+        prefault(map_ptr, map_size.get());
+    }
+    Ok(map_ptr)
+}
+
+/// Maps the whole object behind `fd` at `addr`, failing with `EEXIST` when something is already mapped there
+///
+/// With `guard`, the pages right before and after the mapping must be free as well
+fn map_fd_at(
+    fd: RawFd,
+    map_size: NonZeroUsize,
+    prot: ProtFlags,
+    addr: NonZeroUsize,
+    offset: u64,
+    guard: bool,
+) -> nix::Result<*mut u8> {
+    if guard {
+        let addr = reserve_guarded(map_size, Some(addr))?;
+        return map_in_reservation(fd, map_size, prot, MapFlags::MAP_SHARED, addr, offset);
+    }
+    // Kernels that do not know MAP_FIXED_NOREPLACE take the address as a hint, like other platforms do
+    #[cfg(target_os = "linux")]
+    let flags = MapFlags::MAP_SHARED | MapFlags::MAP_FIXED_NOREPLACE;
+    #[cfg(not(target_os = "linux"))]
+    let flags = MapFlags::MAP_SHARED;
+    let map_ptr = unsafe { mmap_fd(Some(addr), map_size, prot, flags, fd, offset)? };
+    trace!(
+        "mmap({:#x}, {}, {:X}, {:X}, {}, {}) == {:p}",
+        addr,
+        map_size,
+        prot,
+        flags,
+        fd,
+        offset,
+        map_ptr
+    );
+    if map_ptr as usize != addr.get() {
+        let _ = unsafe { munmap(map_ptr, map_size.get()) };
+        return Err(nix::Error::EEXIST);
+    }
+    Ok(map_ptr as *mut _)
+}
+
+/// Returns the size of a mapping of `map_size` bytes along with its guard pages
+fn guarded_len(map_size: usize) -> usize {
+    let page = page_size();
+    map_size.div_ceil(page) * page + 2 * page
+}
+
+/// Reserves inaccessible address space for a mapping of `map_size` bytes and a guard page on each side of it
+///
+/// Returns the address where the mapping goes, which is `addr` when one is requested. Fails with `EEXIST` when
+/// the range around `addr` is in use
+fn reserve_guarded(
+    map_size: NonZeroUsize,
+    addr: Option<NonZeroUsize>,
+) -> nix::Result<NonZeroUsize> {
+    let page = page_size();
+    let len = NonZeroUsize::new(guarded_len(map_size.get())).ok_or(nix::Error::ENOMEM)?;
+    let hint = match addr {
+        Some(addr) => {
+            Some(NonZeroUsize::new(addr.get().saturating_sub(page)).ok_or(nix::Error::EINVAL)?)
+        }
+        None => None,
+    };
+    #[allow(unused_mut)]
+    let mut flags = MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS;
+    #[cfg(target_os = "linux")]
+    if hint.is_some() {
+        flags |= MapFlags::MAP_FIXED_NOREPLACE;
+    }
+    let res_ptr = unsafe { mmap_fd(hint, len, ProtFlags::PROT_NONE, flags, -1, 0)? };
+    trace!(
+        "mmap({:?}, {}, PROT_NONE, {:X}, -1, 0) == {:p}",
+        hint,
+        len,
+        flags,
+        res_ptr
+    );
+    if hint.is_some_and(|hint| hint.get() != res_ptr as usize) {
+        let _ = unsafe { munmap(res_ptr, len.get()) };
+        return Err(nix::Error::EEXIST);
+    }
+    NonZeroUsize::new(res_ptr as usize + page).ok_or(nix::Error::ENOMEM)
+}
+
+/// Maps the object behind `fd` over the middle of a reservation made by `reserve_guarded()`,
+/// releasing the reservation on failure
+fn map_in_reservation(
+    fd: RawFd,
+    map_size: NonZeroUsize,
+    prot: ProtFlags,
+    flags: MapFlags,
+    addr: NonZeroUsize,
+    offset: u64,
+) -> nix::Result<*mut u8> {
+    let flags = flags | MapFlags::MAP_FIXED;
+    match unsafe { mmap_fd(Some(addr), map_size, prot, flags, fd, offset) } {
+        Ok(map_ptr) => {
             trace!(
-                "mmap(NULL, {}, {:X}, {:X}, {}, 0) == {:p}",
-                new_map.map_size,
-                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
-                MapFlags::MAP_SHARED,
-                new_map.map_fd,
-                v
+                "mmap({:#x}, {}, {:X}, {:X}, {}, {}) == {:p}",
+                addr,
+                map_size,
+                prot,
+                flags,
+                fd,
+                offset,
+                map_ptr
             );
-            v as *mut _
+            Ok(map_ptr as *mut u8)
+        }
+        Err(e) => {
+            let _ = unsafe { unmap(addr.get() as *mut u8, map_size.get(), true) };
+            Err(e)
         }
-        Err(e) => return Err(ShmemError::MapOpenFailed(e as u32)),
+    }
+}
+
+/// Unmaps a mapping made by `map_fd()` or `map_fd_at()`, along with its guard pages when it has some
+unsafe fn unmap(map_ptr: *mut u8, map_size: usize, guarded: bool) -> nix::Result<()> {
+    let (ptr, len) = if guarded {
+        (map_ptr.sub(page_size()), guarded_len(map_size))
+    } else {
+        (map_ptr, map_size)
     };
+    trace!("munmap({:p}, {})", ptr, len);
+    munmap(ptr as *mut _, len)
+}
 
-    Ok(new_map)
+/// Calls mmap() with a 64 bit offset, which 32 bit linux targets need to reach past the first 2GiB of an object
+unsafe fn mmap_fd(
+    addr: Option<NonZeroUsize>,
+    map_size: NonZeroUsize,
+    prot: ProtFlags,
+    flags: MapFlags,
+    fd: RawFd,
+    offset: u64,
+) -> nix::Result<*mut libc::c_void> {
+    let addr = addr.map_or(null_mut(), |a| a.get() as *mut libc::c_void);
+    let offset = offset.try_into().map_err(|_| nix::Error::EOVERFLOW)?;
+    #[cfg(target_os = "linux")]
+    let map_ptr = libc::mmap64(addr, map_size.get(), prot.bits(), flags.bits(), fd, offset);
+    #[cfg(not(target_os = "linux"))]
+    let map_ptr = libc::mmap(addr, map_size.get(), prot.bits(), flags.bits(), fd, offset);
+    if map_ptr == libc::MAP_FAILED {
+        return Err(nix::Error::last());
+    }
+    Ok(map_ptr)
+}
+
+/// Returns the size of the object behind `fd`, 32 bit linux targets need fstat64() for objects over 2GiB
+fn object_size(fd: RawFd) -> nix::Result<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut st: libc::stat64 = unsafe { std::mem::zeroed() };
+        nix::Error::result(unsafe { libc::fstat64(fd, &mut st) })?;
+        Ok(st.st_size as u64)
+    }
+    #[cfg(not(target_os = "linux"))]
+    Ok(fstat(fd)?.st_size as u64)
+}
+
+/// Touches every page of a mapping so the first accesses of the user do not fault
+fn prefault(map_ptr: *mut u8, map_size: usize) {
+    for offset in (0..map_size).step_by(page_size()) {
+        unsafe { std::ptr::read_volatile(map_ptr.add(offset)) };
+    }
+}
+
+/// Size in bits of the node masks passed to the NUMA syscalls
+#[cfg(target_os = "linux")]
+const NODE_MASK_BITS: usize = 1024;
+/// Makes get_mempolicy() return the nodes we are allowed to allocate from, libc does not define it
+#[cfg(target_os = "linux")]
+const MPOL_F_MEMS_ALLOWED: libc::c_int = 1 << 2;
+
+/// Sets the NUMA policy of the pages of a mapping, pages that are already allocated stay where they are
+#[cfg(target_os = "linux")]
+fn bind_pages(map_ptr: *mut u8, map_size: usize, policy: NumaPolicy) -> nix::Result<()> {
+    const WORD_BITS: usize = libc::c_ulong::BITS as usize;
+    let mut mask = [0 as libc::c_ulong; NODE_MASK_BITS / WORD_BITS];
+    let mode = match policy {
+        NumaPolicy::Node(node) => {
+            let node = node as usize;
+            if node >= NODE_MASK_BITS {
+                return Err(nix::Error::EINVAL);
+            }
+            mask[node / WORD_BITS] |= 1 << (node % WORD_BITS);
+            libc::MPOL_BIND
+        }
+        NumaPolicy::Interleave => {
+            // Spread the pages over every node we are allowed to use
+            nix::Error::result(unsafe {
+                libc::syscall(
+                    libc::SYS_get_mempolicy,
+                    null_mut::<libc::c_int>(),
+                    mask.as_mut_ptr(),
+                    NODE_MASK_BITS,
+                    null_mut::<libc::c_void>(),
+                    MPOL_F_MEMS_ALLOWED,
+                )
+            })?;
+            libc::MPOL_INTERLEAVE
+        }
+    };
+    trace!("mbind({:p}, {}, {:?})", map_ptr, map_size, policy);
+    // The kernel ignores the last bit of the mask
+    nix::Error::result(unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            map_ptr,
+            map_size,
+            mode,
+            mask.as_ptr(),
+            NODE_MASK_BITS + 1,
+            0,
+        )
+    })?;
+    Ok(())
+}
+
+/// Applies a NUMA policy to pages that were not touched yet, then faults them in if `populate()` is set
+#[cfg(target_os = "linux")]
+fn place_pages(
+    map_ptr: *mut u8,
+    map_size: usize,
+    policy: NumaPolicy,
+    unique_id: &str,
+    ext: &ShmemConfExt,
+    wrap: fn(&'static str, &str, nix::Error) -> ShmemError,
+) -> Result<(), ShmemError> {
+    if let Err(e) =
+        injected(&ext.faults, "mbind").and_then(|_| bind_pages(map_ptr, map_size, policy))
+    {
+        return Err(wrap("mbind", unique_id, e));
+    }
+    // Locking faults the pages in
+    if ext.populate && !ext.lock {
+        prefault(map_ptr, map_size);
+    }
+    Ok(())
+}
+
+/// Applies `lock_in_memory()` and `populate()` to pages that were mapped without `map_fd()`
+fn prepare_pages(
+    map_ptr: *mut u8,
+    map_size: usize,
+    unique_id: &str,
+    ext: &ShmemConfExt,
+    wrap: fn(&'static str, &str, nix::Error) -> ShmemError,
+) -> Result<(), ShmemError> {
+    #[cfg(target_os = "linux")]
+    if let Some(policy) = ext.numa {
+        if let Err(e) =
+            injected(&ext.faults, "mbind").and_then(|_| bind_pages(map_ptr, map_size, policy))
+        {
+            return Err(wrap("mbind", unique_id, e));
+        }
+    }
+    if ext.lock {
+        // Locking faults the pages in
+        return lock_pages(map_ptr, map_size, unique_id, &ext.faults, wrap);
+    }
+    if ext.populate {
+        prefault(map_ptr, map_size);
+    }
+    Ok(())
+}
+
+/// Allocates the storage of `len` bytes of the object behind `fd` from `offset`
+///
+/// Fails with `ShmemError::InsufficientSpace` when its filesystem is full
+#[cfg_attr(
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd"
+    )),
+    allow(unused_variables)
+)]
+fn preallocate(
+    fd: RawFd,
+    offset: usize,
+    len: usize,
+    unique_id: &str,
+    faults: &Faults,
+    wrap: fn(&'static str, &str, nix::Error) -> ShmemError,
+) -> Result<(), ShmemError> {
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd"
+    ))]
+    {
+        trace!("posix_fallocate({}, {}, {})", fd, offset, len);
+        let res = injected(faults, "posix_fallocate").and_then(|_| {
+            match (offset.try_into(), len.try_into()) {
+                (Ok(offset), Ok(len)) => nix::fcntl::posix_fallocate(fd, offset, len),
+                _ => Err(nix::Error::EFBIG),
+            }
+        });
+        match res {
+            Ok(()) => Ok(()),
+            Err(nix::Error::ENOSPC) => match nix::sys::statvfs::fstatvfs(&fd) {
+                Ok(stat) => Err(ShmemError::InsufficientSpace {
+                    required: len,
+                    available: free_bytes(&stat),
+                }),
+                Err(_) => Err(wrap("posix_fallocate", unique_id, nix::Error::ENOSPC)),
+            },
+            Err(e) => Err(wrap("posix_fallocate", unique_id, e)),
+        }
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd"
+    )))]
+    Err(ShmemError::Unsupported("Preallocating shared memory"))
+}
+
+/// Result of a checked copy, with how many bytes were copied, the failing syscall and its error on failure
+#[cfg(target_os = "linux")]
+type CopyResult = Result<(), (usize, &'static str, nix::Error)>;
+
+/// Copies `len` bytes from `src` to `dst` within our address space through the kernel
+///
+/// Pages that would raise `SIGBUS` when touched make the kernel fail with `EFAULT` instead. Sandboxes such as
+/// seccomp filters or gVisor can deny `process_vm_readv()`, the copy then goes through a pipe
+#[cfg(target_os = "linux")]
+fn copy_checked(dst: *mut u8, src: *const u8, len: usize) -> CopyResult {
+    let pid = std::process::id() as libc::pid_t;
+    let mut done = 0;
+    while done < len {
+        let local = libc::iovec {
+            iov_base: unsafe { dst.add(done) } as *mut _,
+            iov_len: len - done,
+        };
+        let remote = libc::iovec {
+            iov_base: unsafe { src.add(done) } as *mut _,
+            iov_len: len - done,
+        };
+        // process_vm_readv() reads the remote iovecs into the local ones, either side can fault
+        let res = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+        match res {
+            -1 => match nix::Error::last() {
+                nix::Error::EPERM | nix::Error::ENOSYS => {
+                    debug!("process_vm_readv() is not allowed, copying through a pipe");
+                    let (dst, src) = unsafe { (dst.add(done), src.add(done)) };
+                    return copy_through_pipe(dst, src, len - done)
+                        .map_err(|(n, syscall, e)| (done + n, syscall, e));
+                }
+                e => return Err((done, "process_vm_readv", e)),
+            },
+            0 => return Err((done, "process_vm_readv", nix::Error::EFAULT)),
+            n => done += n as usize,
+        }
+    }
+    Ok(())
+}
+
+/// Copies `len` bytes from `src` to `dst` by writing them to a pipe and reading them back
+///
+/// Like `process_vm_readv()`, `write()` and `read()` fail with `EFAULT` on pages that would raise `SIGBUS`. They
+/// drop the bytes of a page they fail on so each chunk stays within a single page of `src` and `dst`.
+#[cfg(target_os = "linux")]
+fn copy_through_pipe(dst: *mut u8, src: *const u8, len: usize) -> CopyResult {
+    let mut fds = [0; 2];
+    // The pipe might hold less than a chunk, never block on it
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } == -1 {
+        return Err((0, "pipe2", nix::Error::last()));
+    }
+    let (rd, wr) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    let page = page_size();
+    let mut done = 0;
+    while done < len {
+        let (dst, src) = unsafe { (dst.add(done), src.add(done)) };
+        let chunk = (len - done)
+            .min(page - src as usize % page)
+            .min(page - dst as usize % page);
+        let written = match unsafe { libc::write(wr.as_raw_fd(), src as *const _, chunk) } {
+            -1 => return Err((done, "write", nix::Error::last())),
+            n => n as usize,
+        };
+        let mut read = 0;
+        while read < written {
+            let res =
+                unsafe { libc::read(rd.as_raw_fd(), dst.add(read) as *mut _, written - read) };
+            match res {
+                -1 => return Err((done + read, "read", nix::Error::last())),
+                n => read += n as usize,
+            }
+        }
+        done += written;
+    }
+    Ok(())
+}
+
+/// Returns how many bytes unprivileged users can still allocate on a filesystem
+// The width of the fields depends on the platform
+#[allow(clippy::unnecessary_cast)]
+fn free_bytes(stat: &Statvfs) -> u64 {
+    stat.blocks_available() as u64 * stat.fragment_size() as u64
+}
+
+/// Locks the pages of a mapping into RAM
+fn lock_pages(
+    map_ptr: *mut u8,
+    map_size: usize,
+    unique_id: &str,
+    faults: &Faults,
+    wrap: fn(&'static str, &str, nix::Error) -> ShmemError,
+) -> Result<(), ShmemError> {
+    trace!("mlock({:p}, {})", map_ptr, map_size);
+    match injected(faults, "mlock").and_then(|_| unsafe { mlock(map_ptr as *const _, map_size) }) {
+        Ok(_) => Ok(()),
+        // Unprivileged processes cannot lock more than RLIMIT_MEMLOCK
+        Err(e @ (nix::Error::ENOMEM | nix::Error::EPERM)) => match memlock_limit() {
+            Some(limit) => Err(ShmemError::MemoryLockLimit {
+                required: map_size,
+                limit,
+            }),
+            None => Err(wrap("mlock", unique_id, e)),
+        },
+        Err(e) => Err(wrap("mlock", unique_id, e)),
+    }
+}
+
+/// Returns the amount of memory the process can lock, None when unlimited
+fn memlock_limit() -> Option<u64> {
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+    Some(limit.rlim_cur as u64)
+}
+
+impl ShmemConf {
+    /// Backs the mapping with a regular file at `path` instead of a shared memory object
+    ///
+    /// The path is used as the os_id of the mapping and `open()` maps the same file. This allows the contents to
+    /// live on disk and survive reboots, see `Shmem::flush()`. The file is deleted when its owner drops the mapping
+    /// like any other mapping, use `Shmem::set_owner(false)` to keep it.
+    pub fn file_backed<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.os_id = Some(path.as_ref().to_string_lossy().into_owned());
+        self.ext.file_backed = true;
+        self
+    }
+
+    /// Uses a System V shared memory segment identified by `key` instead of a POSIX shared memory object
+    ///
+    /// This is meant to share memory with programs that use `shmget()`/`shmat()`. The key is used as the os_id
+    /// of the mapping, formatted in hexadecimal like `ipcs` does. The segment is removed with `IPC_RMID`
+    /// when its owner drops the mapping. These mappings cannot be resized nor sent with `Shmem::send_fd()`
+    pub fn sysv(mut self, key: i32) -> Self {
+        self.os_id = Some(sysv::format_key(key));
+        self.ext.sysv = true;
+        self
+    }
+
+    /// Creates the mapping from an anonymous memory file instead of a named shared memory object
+    ///
+    /// These mappings never show up in `/dev/shm` so they cannot be leaked by a crashing process.
+    /// They cannot be opened by id, other processes get access through `Shmem::send_fd()`
+    #[cfg(target_os = "linux")]
+    pub fn memfd(mut self) -> Self {
+        self.ext.memfd = true;
+        self
+    }
+
+    /// Backs the mapping with huge pages to reduce TLB misses on large mappings
+    ///
+    /// The size of the mapping is rounded up to a multiple of the page size, including by `Shmem::resize()`,
+    /// and `Shmem::len()` reports the rounded size.
+    /// Like `memfd()`, the mapping cannot be opened by id and has to be shared through `Shmem::send_fd()`.
+    ///
+    /// `create()` fails with `ShmemError::HugePagesUnavailable` when the kernel has no huge pages of this size to spare
+    /// (see `/proc/sys/vm/nr_hugepages`), unless `huge_pages_fallback()` is set.
+    #[cfg(target_os = "linux")]
+    pub fn huge_pages(mut self, page_size: HugePageSize) -> Self {
+        self.ext.huge_pages = Some(page_size);
+        self
+    }
+
+    /// Falls back to regular pages when `huge_pages()` cannot be satisfied
+    #[cfg(target_os = "linux")]
+    pub fn huge_pages_fallback(mut self) -> Self {
+        self.ext.huge_pages_fallback = true;
+        self
+    }
+
+    /// Locks the pages of the mapping into RAM so they are never swapped out
+    ///
+    /// This applies to mappings made by both `create()` and `open()`, the pages are faulted in as they get locked.
+    /// Unprivileged processes can only lock up to `RLIMIT_MEMLOCK` bytes (see `ulimit -l`), going over it fails
+    /// with `ShmemError::MemoryLockLimit`
+    pub fn lock_in_memory(mut self) -> Self {
+        self.ext.lock = true;
+        self
+    }
+
+    /// Faults all the pages of the mapping in when it is mapped
+    ///
+    /// This moves the cost of the page faults to `create()` and `open()` instead of the first access to each page
+    pub fn populate(mut self) -> Self {
+        self.ext.populate = true;
+        self
+    }
+
+    /// Allocates the pages of the mapping from the memory of the NUMA node `node`
+    ///
+    /// The policy is applied before the pages are first touched, by `create()` as well as `open()`. Pages of shared
+    /// memory objects follow it whichever process faults them in, pages that are already allocated stay where they
+    /// are. `Shmem::numa_nodes()` reports where the pages ended up.
+    #[cfg(target_os = "linux")]
+    pub fn numa_node(mut self, node: u32) -> Self {
+        self.ext.numa = Some(NumaPolicy::Node(node));
+        self
+    }
+
+    /// Spreads the pages of the mapping evenly over the NUMA nodes this process is allowed to use
+    ///
+    /// See `numa_node()`
+    #[cfg(target_os = "linux")]
+    pub fn numa_interleave(mut self) -> Self {
+        self.ext.numa = Some(NumaPolicy::Interleave);
+        self
+    }
+
+    /// Allocates all the storage of the mapping in `create()` and `Shmem::resize()`
+    ///
+    /// Shared memory objects are otherwise sparse : their pages are only allocated when they are first written to,
+    /// and a write that finds the filesystem full (e.g. a small `/dev/shm` in a container) crashes the process with
+    /// `SIGBUS`. With this option, `create()` fails with `ShmemError::InsufficientSpace` instead, see `free_space()`.
+    ///
+    /// System V segments are always allocated up front and ignore it. Not supported on MacOS
+    pub fn preallocate(mut self) -> Self {
+        self.ext.preallocate = true;
+        self
+    }
+
+    /// Returns how many bytes are free on the filesystem that backs the mappings made by `create()`
+    ///
+    /// Shared memory objects live in `/dev/shm` on linux while `file_backed()` mappings use the filesystem of their
+    /// file. Other kinds of mappings are not backed by a filesystem that can be queried
+    pub fn free_space(&self) -> Result<u64, ShmemError> {
+        let path = if self.ext.file_backed {
+            let os_id = self.os_id.as_deref().ok_or(ShmemError::NoLinkOrOsId)?;
+            match Path::new(os_id).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            }
+        } else if cfg!(target_os = "linux")
+            && !self.ext.sysv
+            && !self.ext.memfd
+            && !self.ext.is_huge()
+        {
+            PathBuf::from("/dev/shm")
+        } else {
+            return Err(ShmemError::Unsupported(
+                "Querying the free space of this kind of mapping",
+            ));
+        };
+        match statvfs(&path) {
+            Ok(stat) => Ok(free_bytes(&stat)),
+            Err(e) => Err(mapping_error("statvfs", &path.to_string_lossy(), e)),
+        }
+    }
+
+    /// Surrounds the mapping with inaccessible guard pages
+    ///
+    /// Reading or writing just before `as_ptr()` or past the last page of the mapping then crashes with `SIGSEGV`
+    /// instead of corrupting whatever happens to be mapped next to it. This only costs address space. Accesses past
+    /// `len()` that stay within the last page are not caught as the mapping is rounded up to whole pages.
+    ///
+    /// System V segments and huge pages cannot be guarded
+    pub fn guard_pages(mut self) -> Self {
+        self.ext.guard_pages = true;
+        self
+    }
+
+    /// Receives a mapping that was sent by `Shmem::send_fd()`
+    ///
+    /// The returned mapping is not owned by this process
+    pub fn recv_fd(mut self, stream: &UnixStream) -> Result<Shmem, ShmemError> {
+        let mut buf = [0u8; 4096];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        #[cfg(not(target_os = "macos"))]
+        let flags = MsgFlags::MSG_CMSG_CLOEXEC;
+        #[cfg(target_os = "macos")]
+        let flags = MsgFlags::empty();
+
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let msg = match recvmsg::<()>(stream.as_raw_fd(), &mut iov, Some(&mut cmsg_buf), flags) {
+            Ok(v) => v,
+            Err(e) => return Err(open_error("recvmsg", "", e)),
+        };
+        let mut fds = msg.cmsgs().flat_map(|c| match c {
+            ControlMessageOwned::ScmRights(fds) => fds,
+            _ => Vec::new(),
+        });
+        let fd = fds.next();
+        // Dont leak extra descriptors a misbehaving peer might have sent
+        for extra_fd in fds {
+            let _ = close(extra_fd);
+        }
+        let bytes = msg.bytes;
+
+        let os_id = String::from_utf8_lossy(&buf[..bytes]).into_owned();
+        let fd = match fd {
+            Some(fd) => fd,
+            None => {
+                return Err(ShmemError::MapOpenFailed {
+                    syscall: "recvmsg",
+                    os_id,
+                    source: io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "no file descriptor was received",
+                    ),
+                })
+            }
+        };
+        trace!("recvmsg({}) == {} '{}'", stream.as_raw_fd(), fd, os_id);
+
+        self.os_id = Some(os_id);
+        self.open_fd(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Maps an existing shared memory file descriptor
+    ///
+    /// The returned mapping is not owned by this process and is identified by the os_id set in this config
+    pub fn open_fd(mut self, fd: OwnedFd) -> Result<Shmem, ShmemError> {
+        let unique_id = match self.os_id {
+            Some(ref id) => id.clone(),
+            None => format!("fd:{}", fd.as_raw_fd()),
+        };
+        debug!("Openning mapping {} from file descriptor", unique_id);
+
+        // Sealed memfds cannot be mapped with write access
+        #[cfg(target_os = "linux")]
+        if get_seals(fd.as_raw_fd()).is_ok_and(|s| s.contains(SealFlag::F_SEAL_WRITE)) {
+            debug!("Mapping is sealed, openning as read-only");
+            self.read_only = true;
+        }
+
+        self.check_window()?;
+        let window = self.window();
+        let mapping = map_existing(
+            fd.into_raw_fd(),
+            ObjectKind::Anonymous,
+            unique_id,
+            window,
+            self.read_only,
+            &self.ext,
+        )?;
+        self.into_opened(Box::new(mapping))
+    }
+}
+
+/// Seals applied by `Shmem::seal()`
+#[cfg(target_os = "linux")]
+const SEALS: SealFlag = SealFlag::F_SEAL_WRITE
+    .union(SealFlag::F_SEAL_SHRINK)
+    .union(SealFlag::F_SEAL_GROW);
+
+/// Returns the seals currently applied to a memfd
+#[cfg(target_os = "linux")]
+fn get_seals(fd: RawFd) -> nix::Result<SealFlag> {
+    let seals = fcntl(fd, FcntlArg::F_GET_SEALS)?;
+    Ok(SealFlag::from_bits_truncate(seals))
+}
+
+/// Replaces the pages of our mapping in place with the ones of `fd` from `offset`, the address of the mapping does
+/// not change
+#[cfg(target_os = "linux")]
+fn remap_in_place(
+    map: &dyn ShmemMapping,
+    fd: RawFd,
+    offset: u64,
+    prot: ProtFlags,
+    flags: MapFlags,
+) -> nix::Result<()> {
+    let map_size = NonZeroUsize::new(map.len()).ok_or(nix::Error::EINVAL)?;
+    let flags = flags | MapFlags::MAP_FIXED;
+    unsafe {
+        mmap_fd(
+            NonZeroUsize::new(map.as_ptr() as usize),
+            map_size,
+            prot,
+            flags,
+            fd,
+            offset,
+        )?
+    };
+    trace!(
+        "mmap({:p}, {}, {:X}, {:X}, {}, {})",
+        map.as_ptr(),
+        map.len(),
+        prot,
+        flags,
+        fd,
+        offset
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+impl Shmem {
+    /// Seals the mapping so that its contents and size can never change again
+    ///
+    /// Only mappings created with `ShmemConf::memfd()` can be sealed and this fails if another process still
+    /// has a writable mapping. The mapping becomes read-only in this process too, which is why it cannot be
+    /// sealed from its init closure.
+    pub fn seal(&mut self) -> Result<(), ShmemError> {
+        if self.is_sealed() {
+            return Ok(());
+        }
+        // The header is still written to when the init closure returns
+        if self.initializing() {
+            return Err(ShmemError::Unsupported(
+                "Sealing mappings during their init closure",
+            ));
+        }
+        let fd = match self.mapping.as_raw_fd().map(|fd| (fd, get_seals(fd))) {
+            Some((fd, Ok(seals))) if !seals.contains(SealFlag::F_SEAL_SEAL) => fd,
+            _ => {
+                return Err(ShmemError::Unsupported(
+                    "Sealing mappings not created with memfd()",
+                ))
+            }
+        };
+
+        // The kernel refuses to seal while writable shared mappings exist, including ours.
+        // Swap our pages for a read-only private mapping which sees the same (soon immutable) contents
+        debug!("Sealing mapping {}", self.get_os_id());
+        let offset = self.config.window().map_or(0, |(offset, _)| offset);
+        if let Err(e) = remap_in_place(
+            self.mapping.as_ref(),
+            fd,
+            offset,
+            ProtFlags::PROT_READ,
+            MapFlags::MAP_PRIVATE,
+        ) {
+            return Err(self.os_error("mmap", e));
+        }
+
+        trace!("fcntl({}, F_ADD_SEALS, {:X})", fd, SEALS);
+        if let Err(e) = fcntl(fd, FcntlArg::F_ADD_SEALS(SEALS)) {
+            // Give our writable mapping back
+            let _ = remap_in_place(
+                self.mapping.as_ref(),
+                fd,
+                offset,
+                prot_flags(false),
+                MapFlags::MAP_SHARED,
+            );
+            return Err(self.os_error("fcntl", e));
+        }
+
+        self.config.read_only = true;
+        Ok(())
+    }
+
+    /// Returns whether the mapping is sealed against writes and size changes
+    pub fn is_sealed(&self) -> bool {
+        match self.mapping.as_raw_fd() {
+            Some(fd) => get_seals(fd).is_ok_and(|s| s.contains(SEALS)),
+            None => false,
+        }
+    }
+
+    /// Returns the contents of a sealed mapping
+    ///
+    /// Unlike `as_slice()`, this is safe as the kernel guarantees that no process can modify the contents of
+    /// a sealed mapping. Returns `None` if the mapping is not sealed, see `seal()`
+    pub fn as_sealed_slice(&self) -> Option<&[u8]> {
+        if !self.is_sealed() {
+            return None;
+        }
+        Some(unsafe { std::slice::from_raw_parts(self.as_ptr(), self.len()) })
+    }
+}
+
+impl Shmem {
+    /// Writes the changes made to a file backed mapping back to disk
+    ///
+    /// This blocks until the data is written, see `ShmemConf::file_backed()`
+    pub fn flush(&self) -> Result<(), ShmemError> {
+        self.flush_range(0..self.len())
+    }
+
+    /// Writes the changes made to a range of a file backed mapping back to disk
+    ///
+    /// The range is relative to `as_ptr()`
+    pub fn flush_range(&self, range: Range<usize>) -> Result<(), ShmemError> {
+        let range = self.map_range(range)?;
+        if range.is_empty() {
+            return Ok(());
+        }
+        // msync() wants a page aligned address
+        let start = range.start - range.start % page_size();
+        let addr = unsafe { self.mapping.as_ptr().add(start) };
+        let len = range.end - start;
+        trace!("msync({:p}, {}, MS_SYNC)", addr, len);
+        if let Err(e) = unsafe { msync(addr as *mut _, len, MsFlags::MS_SYNC) } {
+            return Err(self.os_error("msync", e));
+        }
+        Ok(())
+    }
+
+    /// Tells the kernel how a range of the mapping will be used
+    ///
+    /// The range is relative to `as_ptr()`. `madvise()` works on whole pages, the range is extended to the pages
+    /// it touches except for the advices that discard data (`Advice::DontNeed` and `Advice::Remove`) which only
+    /// apply to the pages that are fully inside of it. Nothing around the range is ever discarded.
+    pub fn advise(&self, range: Range<usize>, advice: Advice) -> Result<(), ShmemError> {
+        let range = self.map_range(range)?;
+        let page = page_size();
+        let (start, end) = if advice.is_destructive() {
+            (
+                range.start.div_ceil(page) * page,
+                range.end - range.end % page,
+            )
+        } else {
+            (range.start - range.start % page, range.end)
+        };
+        if start >= end {
+            return Ok(());
+        }
+        let addr = unsafe { self.mapping.as_ptr().add(start) };
+        trace!("madvise({:p}, {}, {:?})", addr, end - start, advice);
+        if let Err(e) = unsafe { madvise(addr as *mut _, end - start, advice.as_madvise()) } {
+            return Err(self.os_error("madvise", e));
+        }
+        Ok(())
+    }
+
+    /// Returns how many pages of the mapping are allocated on each NUMA node, indexed by node
+    ///
+    /// Pages that were never touched are not allocated yet and are not counted
+    #[cfg(target_os = "linux")]
+    pub fn numa_nodes(&self) -> Result<Vec<usize>, ShmemError> {
+        // Number of pages queried by each move_pages() call
+        const BATCH: usize = 1024;
+        let page = page_size();
+        let base = self.mapping.as_ptr() as usize;
+        let len = self.mapping.len();
+
+        let mut counts = Vec::new();
+        let mut pages = Vec::with_capacity(BATCH);
+        let mut status: Vec<libc::c_int> = Vec::with_capacity(BATCH);
+        for start in (0..len).step_by(page * BATCH) {
+            let end = len.min(start + page * BATCH);
+            pages.clear();
+            pages.extend(
+                (start..end)
+                    .step_by(page)
+                    .map(|o| (base + o) as *mut libc::c_void),
+            );
+            status.clear();
+            status.resize(pages.len(), 0);
+            // Without target nodes, move_pages() only reports where each page is
+            if let Err(e) = nix::Error::result(unsafe {
+                libc::syscall(
+                    libc::SYS_move_pages,
+                    0,
+                    pages.len(),
+                    pages.as_ptr(),
+                    null_mut::<libc::c_int>(),
+                    status.as_mut_ptr(),
+                    0,
+                )
+            }) {
+                return Err(self.os_error("move_pages", e));
+            }
+            // Pages that are not allocated report a negative errno
+            for node in status.iter().filter_map(|&s| usize::try_from(s).ok()) {
+                if counts.len() <= node {
+                    counts.resize(node + 1, 0);
+                }
+                counts[node] += 1;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Changes the access allowed to a range of the mapping in our address space
+    ///
+    /// This lets a creator freeze a region once it is initialized, or catch stray writes in debug builds. Other
+    /// processes are not affected. The range is relative to `as_ptr()` and is extended to the pages it touches as
+    /// `mprotect()` works on whole pages. Protections are lost when `resize()` or `refresh()` map the object again.
+    ///
+    /// Mappings opened with `read_only()` cannot be made writable. The page holding the managed header cannot be
+    /// protected, nor can mappings from the init closure or from backends that do not map whole pages such as
+    /// `HeapBackend`.
+    ///
+    /// # Safety
+    ///
+    /// The pages covered by the range must not be accessed in a way the new protection forbids. This includes
+    /// slices previously returned by `as_slice()`, `as_slice_mut()` or `as_sealed_slice()` that are still in use.
+    pub unsafe fn protect(
+        &self,
+        range: Range<usize>,
+        protection: Protection,
+    ) -> Result<(), ShmemError> {
+        if self.is_read_only() && protection == Protection::ReadWrite {
+            return Err(ShmemError::Unsupported("Writing to read-only mappings"));
+        }
+        // The header is still written to when the init closure returns
+        if self.initializing() {
+            return Err(ShmemError::Unsupported(
+                "Protecting mappings during their init closure",
+            ));
+        }
+        let range = self.map_range(range)?;
+        if range.is_empty() {
+            return Ok(());
+        }
+        let start = range.start - range.start % page_size();
+        // Every process reads the header and resize() writes it
+        if start < self.config.header_size() {
+            return Err(ShmemError::Unsupported(
+                "Protecting the page of the managed header",
+            ));
+        }
+        self.mapping.protect(start..range.end, protection)
+    }
+
+    /// Copies the bytes at `offset` into `buf`, failing instead of crashing when the pages are gone
+    ///
+    /// Reading through `as_ptr()` crashes the process with `SIGBUS` when another process truncated the object or
+    /// its filesystem ran out of space. This copy is made by the kernel which reports such pages, and fails with
+    /// `ShmemError::PageFault` at the first byte that could not be read. No signal handler is involved so it is safe
+    /// to use from any thread. `offset` is relative to `as_ptr()`, `buf` may be partially filled on failure.
+    ///
+    /// The copy is made with `process_vm_readv()`. When a sandbox denies it with `EPERM` or `ENOSYS`, such as
+    /// seccomp filters or gVisor, the bytes go through a pipe instead which is slower but fails the same way.
+    #[cfg(target_os = "linux")]
+    pub fn try_read_into(&self, offset: usize, buf: &mut [u8]) -> Result<(), ShmemError> {
+        let range = self.map_range(offset..offset.saturating_add(buf.len()))?;
+        let src = unsafe { self.mapping.as_ptr().add(range.start) };
+        trace!("copy_checked({:p}, {:p}, {})", buf.as_ptr(), src, buf.len());
+        copy_checked(buf.as_mut_ptr(), src, buf.len()).map_err(|e| self.fault_error(offset, e))
+    }
+
+    /// Copies `data` into the mapping at `offset`, failing instead of crashing when the pages are gone
+    ///
+    /// See `try_read_into()`, the mapping may be partially written on failure
+    #[cfg(target_os = "linux")]
+    pub fn try_write_from(&mut self, offset: usize, data: &[u8]) -> Result<(), ShmemError> {
+        if self.is_read_only() {
+            return Err(ShmemError::Unsupported("Writing to read-only mappings"));
+        }
+        let range = self.map_range(offset..offset.saturating_add(data.len()))?;
+        let dst = unsafe { self.mapping.as_ptr().add(range.start) };
+        trace!(
+            "copy_checked({:p}, {:p}, {})",
+            dst,
+            data.as_ptr(),
+            data.len()
+        );
+        copy_checked(dst, data.as_ptr(), data.len()).map_err(|e| self.fault_error(offset, e))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn fault_error(
+        &self,
+        offset: usize,
+        (done, syscall, e): (usize, &'static str, nix::Error),
+    ) -> ShmemError {
+        match e {
+            nix::Error::EFAULT => ShmemError::PageFault {
+                offset: offset + done,
+            },
+            e => self.os_error(syscall, e),
+        }
+    }
+
+    /// Sends the file descriptor of the mapping to the peer of a unix socket
+    ///
+    /// The peer rebuilds the mapping with `ShmemConf::recv_fd()`, this is the only way to share mappings
+    /// created with `ShmemConf::memfd()`
+    pub fn send_fd(&self, stream: &UnixStream) -> Result<(), ShmemError> {
+        let fd = match self.mapping.as_raw_fd() {
+            Some(fd) => fd,
+            None => {
+                return Err(ShmemError::Unsupported(
+                    "Sending mappings without a file descriptor",
+                ))
+            }
+        };
+        let fds = [fd];
+        let iov = [IoSlice::new(self.get_os_id().as_bytes())];
+        let cmsgs = [ControlMessage::ScmRights(&fds)];
+        trace!("sendmsg({}, {})", stream.as_raw_fd(), fd);
+        match sendmsg::<()>(stream.as_raw_fd(), &iov, &cmsgs, MsgFlags::empty(), None) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self.os_error("sendmsg", e)),
+        }
+    }
+
+    fn os_error(&self, syscall: &'static str, e: nix::Error) -> ShmemError {
+        ShmemError::OsError {
+            syscall,
+            os_id: self.get_os_id().to_string(),
+            source: e.into(),
+        }
+    }
+}
+
+/// Returns the size of the pages of our address space
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// libc does not define it for linux
+#[cfg(target_os = "linux")]
+const PTHREAD_MUTEX_ROBUST: libc::c_int = 1;
+
+#[cfg(target_os = "linux")]
+const _: () = assert!(std::mem::size_of::<libc::pthread_mutex_t>() <= 64);
+
+/// Initializes and takes the lock held by the creator of a mapping during its initialization
+///
+/// The lock is a robust mutex : when the creator dies, the kernel marks it as abandoned for every process that maps
+/// it, whatever their PID namespace. Returns false when the lock is not available on this platform
+///
+/// # Safety
+/// `lock` must point to 64 bytes of shared memory aligned on 8 bytes that are not used by anyone else
+pub unsafe fn lock_init(lock: *mut u8) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let mutex = lock as *mut libc::pthread_mutex_t;
+        let mut attr: libc::pthread_mutexattr_t = std::mem::zeroed();
+        if libc::pthread_mutexattr_init(&mut attr) != 0 {
+            return false;
+        }
+        let res = if libc::pthread_mutexattr_setpshared(&mut attr, libc::PTHREAD_PROCESS_SHARED)
+            == 0
+            && libc::pthread_mutexattr_setrobust(&mut attr, PTHREAD_MUTEX_ROBUST) == 0
+            && libc::pthread_mutex_init(mutex, &attr) == 0
+        {
+            libc::pthread_mutex_lock(mutex) == 0
+        } else {
+            false
+        };
+        libc::pthread_mutexattr_destroy(&mut attr);
+        res
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = lock;
+        false
+    }
+}
+
+/// Releases the lock taken by `lock_init()`
+///
+/// # Safety
+/// Must be called by the thread that got true from `lock_init()`
+pub unsafe fn unlock_init(lock: *mut u8) {
+    #[cfg(target_os = "linux")]
+    libc::pthread_mutex_unlock(lock as *mut libc::pthread_mutex_t);
+    #[cfg(not(target_os = "linux"))]
+    let _ = lock;
+}
+
+/// Returns whether the creator died while holding the lock taken by `lock_init()`
+///
+/// Only reports abandoned locks, openers rely on their timeout when the lock is not available
+///
+/// # Safety
+/// `lock` must have been initialized by `lock_init()`
+pub unsafe fn init_abandoned(lock: *mut u8) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let mutex = lock as *mut libc::pthread_mutex_t;
+        match libc::pthread_mutex_trylock(mutex) {
+            // The creator released it between our check of the state and now
+            0 => {
+                libc::pthread_mutex_unlock(mutex);
+                false
+            }
+            // Leave the mutex inconsistent so every other opener notices as well
+            libc::EOWNERDEAD => {
+                libc::pthread_mutex_unlock(mutex);
+                true
+            }
+            libc::ENOTRECOVERABLE => true,
+            _ => false,
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = lock;
+        false
+    }
+}
+
+#[cfg(all(test, target_pointer_width = "32"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_larger_than_address_space() {
+        let e = mapped_range("test", 1 << 33, None).unwrap_err();
+        assert!(matches!(
+            e,
+            ShmemError::MapOpenFailed {
+                syscall: "fstat",
+                ..
+            }
+        ));
+        assert_eq!(
+            mapped_range("test", 1 << 33, Some((0, 4096))).unwrap(),
+            (0, 4096)
+        );
+    }
 }
@@ -1,16 +1,18 @@
 use std::fs::{File, OpenOptions};
-use std::io::ErrorKind;
+use std::io::{self, ErrorKind};
 use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::{log::*, ShmemConf};
 use win_sys::*;
 
-use crate::ShmemError;
+use crate::{Faults, ShmemBackend, ShmemError, ShmemErrorKind, ShmemMapping};
 
 #[derive(Clone, Default)]
 pub struct ShmemConfExt {
     allow_raw: bool,
+    pub(crate) faults: Faults,
 }
 
 impl ShmemConf {
@@ -22,8 +24,6 @@ impl ShmemConf {
 }
 
 pub struct MapData {
-    owner: bool,
-
     /// Pointer to the first byte of our mapping
     /// Keep this above `file_map` so it gets dropped first
     pub view: ViewOfFile,
@@ -44,10 +44,52 @@ pub struct MapData {
     //Total size of the mapping
     pub map_size: usize,
 }
-///Teardown UnmapViewOfFile and close CreateMapping handle
-impl Drop for MapData {
-    ///Takes care of properly closing the SharedMem
-    fn drop(&mut self) {
+
+unsafe impl ShmemMapping for MapData {
+    fn os_id(&self) -> &str {
+        self.unique_id.as_str()
+    }
+    fn len(&self) -> usize {
+        self.map_size
+    }
+    fn as_ptr(&self) -> *mut u8 {
+        self.view.as_mut_ptr() as _
+    }
+}
+
+/// Backend for the file mappings of Windows, configured by the Windows specific options of `ShmemConf`
+struct OsBackend {
+    ext: ShmemConfExt,
+}
+
+/// Returns the backend used when `ShmemConf::backend()` was not called
+pub fn default_backend(ext: &ShmemConfExt) -> Arc<dyn ShmemBackend> {
+    Arc::new(OsBackend { ext: ext.clone() })
+}
+
+impl ShmemBackend for OsBackend {
+    fn create(&self, os_id: &str, size: usize) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        Ok(Box::new(new_map(os_id, size, true, false, false)?))
+    }
+
+    fn open(
+        &self,
+        os_id: &str,
+        size: usize,
+        read_only: bool,
+    ) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        Ok(Box::new(new_map(
+            os_id,
+            size,
+            false,
+            read_only,
+            self.ext.allow_raw,
+        )?))
+    }
+
+    /// Takes care of properly removing the persistent file
+    fn unlink(&self, mapping: &dyn ShmemMapping) -> Result<(), ShmemError> {
+        let os_id = mapping.os_id();
         // Inspired by the boost implementation at
         // https://github.com/boostorg/interprocess/blob/140b50efb3281fa3898f3a4cf939cfbda174718f/include/boost/interprocess/detail/win32_api.hpp
         // Emulate POSIX behavior by
@@ -58,64 +100,50 @@ impl Drop for MapData {
         // deleted once all handles have been closed and no new handles can be opened
         // because the file has been renamed. This matches the behavior of shm_unlink()
         // on unix.
-        if self.owner {
-            let mut base_path = get_tmp_dir().unwrap();
-
-            // 1. Set file attributes so that it deletes itself once everyone has closed it
-            let file_path = base_path.join(self.unique_id.trim_start_matches('/'));
-            debug!("Setting mapping to delete after everyone has closed it");
-            match OpenOptions::new()
-                .access_mode(GENERIC_READ | GENERIC_WRITE | DELETE)
-                .share_mode((FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE).0)
-                .create(false)
-                .attributes((FILE_ATTRIBUTE_TEMPORARY | FILE_FLAG_DELETE_ON_CLOSE).0)
-                .open(&file_path)
-            {
-                Ok(_) => {
-                    // 2. Rename file to prevent further use
-                    base_path.push(&format!(
-                        "{}_deleted",
-                        self.unique_id.trim_start_matches('/')
-                    ));
-                    debug!(
-                        "Renaming {} to {}",
-                        file_path.to_string_lossy(),
-                        base_path.to_string_lossy()
-                    );
-                    if let Err(_e) = std::fs::rename(&file_path, &base_path) {
-                        debug!(
-                            "Failed to rename persistent_file {} : {}",
-                            file_path.to_string_lossy(),
-                            _e
-                        );
-                    }
-                }
-                Err(_e) => {
-                    debug!(
-                        "Failed to set DELETE_ON_CLOSE on persistent_file {} : {}",
-                        file_path.to_string_lossy(),
-                        _e
-                    );
+        let mut base_path = match get_tmp_dir() {
+            Ok(p) => p,
+            Err(e) => return Err(unlink_error("create_dir_all", os_id, e)),
+        };
+
+        // 1. Set file attributes so that it deletes itself once everyone has closed it
+        let file_path = base_path.join(os_id.trim_start_matches('/'));
+        debug!("Setting mapping to delete after everyone has closed it");
+        match OpenOptions::new()
+            .access_mode(GENERIC_READ | GENERIC_WRITE | DELETE)
+            .share_mode((FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE).0)
+            .create(false)
+            .attributes((FILE_ATTRIBUTE_TEMPORARY | FILE_FLAG_DELETE_ON_CLOSE).0)
+            .open(&file_path)
+        {
+            Ok(_) => {
+                // 2. Rename file to prevent further use
+                base_path.push(&format!("{}_deleted", os_id.trim_start_matches('/')));
+                debug!(
+                    "Renaming {} to {}",
+                    file_path.to_string_lossy(),
+                    base_path.to_string_lossy()
+                );
+                if let Err(e) = std::fs::rename(&file_path, &base_path) {
+                    return Err(unlink_error("MoveFile", os_id, e));
                 }
-            };
-        }
+            }
+            Err(e) => return Err(unlink_error("CreateFile", os_id, e)),
+        };
+        Ok(())
     }
 }
 
-impl MapData {
-    pub fn set_owner(&mut self, is_owner: bool) -> bool {
-        let prev_val = self.owner;
-        self.owner = is_owner;
-        prev_val
-    }
-    pub fn as_mut_ptr(&self) -> *mut u8 {
-        self.view.as_mut_ptr() as _
+fn unlink_error(syscall: &'static str, os_id: &str, source: io::Error) -> ShmemError {
+    ShmemError::OsError {
+        syscall,
+        os_id: os_id.to_string(),
+        source,
     }
 }
 
 /// Returns the path to a temporary directory in which to store files backing the shared memory. If it
 /// doesn't exist, the directory is created.
-fn get_tmp_dir() -> Result<PathBuf, ShmemError> {
+fn get_tmp_dir() -> io::Result<PathBuf> {
     debug!("Getting & creating shared_memory-rs temp dir");
     let mut path = std::env::temp_dir();
     path.push("shared_memory-rs");
@@ -127,7 +155,41 @@ fn get_tmp_dir() -> Result<PathBuf, ShmemError> {
     match std::fs::create_dir_all(path.as_path()) {
         Ok(_) => Ok(path),
         Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(path),
-        Err(e) => Err(ShmemError::UnknownOsError(e.raw_os_error().unwrap() as _)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Wraps an OS error into the proper error depending on whether we were creating the mapping
+fn map_error(
+    create: bool,
+    syscall: &'static str,
+    unique_id: &str,
+    source: io::Error,
+) -> ShmemError {
+    let os_id = unique_id.to_string();
+    if create {
+        ShmemError::MapCreateFailed {
+            syscall,
+            os_id,
+            source,
+        }
+    } else {
+        ShmemError::MapOpenFailed {
+            syscall,
+            os_id,
+            source,
+        }
+    }
+}
+
+/// Classifies OS errors that are not covered by `std::io::ErrorKind`
+pub fn error_kind(err: &io::Error) -> ShmemErrorKind {
+    match err.raw_os_error() {
+        // ERROR_HANDLE_DISK_FULL, ERROR_DISK_FULL
+        Some(39) | Some(112) => ShmemErrorKind::NoSpace,
+        // ERROR_TOO_MANY_OPEN_FILES, ERROR_NOT_ENOUGH_MEMORY, ERROR_OUTOFMEMORY, ERROR_COMMITMENT_LIMIT
+        Some(4) | Some(8) | Some(14) | Some(1455) => ShmemErrorKind::ResourceLimit,
+        _ => ShmemErrorKind::Other,
     }
 }
 
@@ -135,10 +197,20 @@ fn new_map(
     unique_id: &str,
     mut map_size: usize,
     create: bool,
+    read_only: bool,
     allow_raw: bool,
 ) -> Result<MapData, ShmemError> {
+    let (page_prot, map_access) = if read_only {
+        (PAGE_READONLY, FILE_MAP_READ)
+    } else {
+        (PAGE_READWRITE, FILE_MAP_READ | FILE_MAP_WRITE)
+    };
+
     // Create file to back the shared memory
-    let mut file_path = get_tmp_dir()?;
+    let mut file_path = match get_tmp_dir() {
+        Ok(p) => p,
+        Err(e) => return Err(map_error(create, "create_dir_all", unique_id, e)),
+    };
     file_path.push(unique_id.trim_start_matches('/'));
     debug!(
         "{} persistent_file at {}",
@@ -148,7 +220,7 @@ fn new_map(
 
     let mut opt = OpenOptions::new();
     opt.read(true)
-        .write(true)
+        .write(!read_only)
         .share_mode((FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE).0)
         .attributes((FILE_ATTRIBUTE_TEMPORARY).0);
     if create {
@@ -170,7 +242,7 @@ fn new_map(
             trace!(
                 "CreateFileMapping({:?}, NULL, {:X}, {}, {}, '{}')",
                 HANDLE(f.as_raw_handle() as _),
-                PAGE_READWRITE.0,
+                page_prot.0,
                 high_size,
                 low_size,
                 unique_id,
@@ -179,7 +251,7 @@ fn new_map(
             match CreateFileMapping(
                 HANDLE(f.as_raw_handle() as _),
                 None,
-                PAGE_READWRITE,
+                page_prot,
                 high_size,
                 low_size,
                 unique_id,
@@ -193,11 +265,12 @@ fn new_map(
                     return if err_code == ERROR_ALREADY_EXISTS {
                         Err(ShmemError::MappingIdExists)
                     } else {
-                        Err(if create {
-                            ShmemError::MapCreateFailed(err_code.0)
-                        } else {
-                            ShmemError::MapOpenFailed(err_code.0)
-                        })
+                        Err(map_error(
+                            create,
+                            "CreateFileMapping",
+                            unique_id,
+                            io::Error::from_raw_os_error(err_code.0 as _),
+                        ))
                     };
                 }
             }
@@ -205,23 +278,38 @@ fn new_map(
         Err(e) if e.kind() == ErrorKind::AlreadyExists => return Err(ShmemError::MappingIdExists),
         Err(e) => {
             if create {
-                return Err(ShmemError::MapCreateFailed(e.raw_os_error().unwrap() as _));
+                return Err(map_error(create, "CreateFile", unique_id, e));
             } else if !allow_raw {
-                return Err(ShmemError::MapOpenFailed(ERROR_FILE_NOT_FOUND.0));
+                return Err(map_error(
+                    create,
+                    "CreateFile",
+                    unique_id,
+                    io::Error::from_raw_os_error(ERROR_FILE_NOT_FOUND.0 as _),
+                ));
             }
 
             // This may be a mapping that isnt managed by this crate
             // Try to open the mapping without any backing file
+            let open_access = if read_only {
+                FILE_MAP_READ
+            } else {
+                FILE_MAP_ALL_ACCESS
+            };
             trace!(
                 "OpenFileMappingW({:?}, {}, '{}')",
-                FILE_MAP_ALL_ACCESS,
+                open_access,
                 false,
                 unique_id,
             );
-            match OpenFileMapping(FILE_MAP_ALL_ACCESS, false, unique_id) {
+            match OpenFileMapping(open_access, false, unique_id) {
                 Ok(h) => h,
                 Err(e) => {
-                    return Err(ShmemError::MapOpenFailed(e.win32_error().unwrap().0));
+                    return Err(map_error(
+                        create,
+                        "OpenFileMapping",
+                        unique_id,
+                        io::Error::from_raw_os_error(e.win32_error().unwrap().0 as _),
+                    ));
                 }
             }
         }
@@ -230,19 +318,16 @@ fn new_map(
 
     //Map mapping into address space
     debug!("Loading mapping into address space");
-    trace!(
-        "MapViewOfFile(0x{:X}, {:X}, 0, 0, 0)",
-        map_h,
-        (FILE_MAP_READ | FILE_MAP_WRITE).0,
-    );
-    let map_ptr = match MapViewOfFile(map_h.as_handle(), FILE_MAP_READ | FILE_MAP_WRITE, 0, 0, 0) {
+    trace!("MapViewOfFile(0x{:X}, {:X}, 0, 0, 0)", map_h, map_access.0,);
+    let map_ptr = match MapViewOfFile(map_h.as_handle(), map_access, 0, 0, 0) {
         Ok(v) => v,
         Err(e) => {
-            return Err(if create {
-                ShmemError::MapCreateFailed(e.win32_error().unwrap().0)
-            } else {
-                ShmemError::MapOpenFailed(e.win32_error().unwrap().0)
-            })
+            return Err(map_error(
+                create,
+                "MapViewOfFile",
+                unique_id,
+                io::Error::from_raw_os_error(e.win32_error().unwrap().0 as _),
+            ))
         }
     };
     trace!("\t{:p}", map_ptr);
@@ -251,13 +336,17 @@ fn new_map(
         //Get the real size of the openned mapping
         let mut info = MEMORY_BASIC_INFORMATION::default();
         if let Err(e) = VirtualQuery(map_ptr.as_mut_ptr(), &mut info) {
-            return Err(ShmemError::UnknownOsError(e.win32_error().unwrap().0));
+            return Err(map_error(
+                create,
+                "VirtualQuery",
+                unique_id,
+                io::Error::from_raw_os_error(e.win32_error().unwrap().0 as _),
+            ));
         }
         map_size = info.RegionSize;
     }
 
     Ok(MapData {
-        owner: create,
         file_map: map_h,
         persistent_file,
         unique_id: unique_id.to_string(),
@@ -266,16 +355,26 @@ fn new_map(
     })
 }
 
-//Creates a mapping specified by the uid and size
-pub fn create_mapping(unique_id: &str, map_size: usize) -> Result<MapData, ShmemError> {
-    new_map(unique_id, map_size, true, false)
+/// Takes the lock held by the creator of a mapping during its initialization
+///
+/// This is not implemented on Windows, openers rely on their timeout instead
+///
+/// # Safety
+/// `lock` must point to the 64 bytes reserved for the lock in the header
+pub unsafe fn lock_init(_lock: *mut u8) -> bool {
+    false
 }
 
-//Opens an existing mapping specified by its uid
-pub fn open_mapping(
-    unique_id: &str,
-    map_size: usize,
-    ext: &ShmemConfExt,
-) -> Result<MapData, ShmemError> {
-    new_map(unique_id, map_size, false, ext.allow_raw)
+/// Releases the lock taken by `lock_init()`
+///
+/// # Safety
+/// Must be called by the thread that got true from `lock_init()`
+pub unsafe fn unlock_init(_lock: *mut u8) {}
+
+/// Returns whether the creator died while holding the lock taken by `lock_init()`
+///
+/// # Safety
+/// `lock` must have been initialized by `lock_init()`
+pub unsafe fn init_abandoned(_lock: *mut u8) -> bool {
+    false
 }
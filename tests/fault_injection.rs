@@ -0,0 +1,303 @@
+#![cfg(unix)]
+
+use shared_memory::{FaultPlan, HeapBackend, ShmemConf, ShmemError, ShmemErrorKind};
+
+fn failed_syscall(e: &ShmemError) -> &'static str {
+    match e {
+        ShmemError::MapCreateFailed { syscall, .. }
+        | ShmemError::MapOpenFailed { syscall, .. }
+        | ShmemError::OsError { syscall, .. } => syscall,
+        _ => panic!("Unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn create_failure() {
+    let plan = FaultPlan::new().fail("create", libc::EMFILE);
+
+    let e = ShmemConf::new()
+        .size(4096)
+        .fault_plan(plan.clone())
+        .create()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "create");
+    assert_eq!(e.kind(), ShmemErrorKind::ResourceLimit);
+    assert!(plan.is_done());
+
+    // Only the planned call fails
+    ShmemConf::new()
+        .size(4096)
+        .fault_plan(plan)
+        .create()
+        .unwrap();
+}
+
+#[test]
+fn create_setup_failure() {
+    let os_id = format!("/shmem_fault_setup_{}", std::process::id());
+    let plan = FaultPlan::new().fail("remap_at", libc::ENOMEM);
+
+    // Moving the new mapping to its fixed address fails after the object was created
+    let e = ShmemConf::new()
+        .size(4096)
+        .os_id(&os_id)
+        .fixed_address(0x7e00_0000_0000)
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "remap_at");
+    assert_eq!(e.kind(), ShmemErrorKind::ResourceLimit);
+
+    // The object must not leak
+    let e = ShmemConf::new().os_id(&os_id).open().err().unwrap();
+    assert_eq!(e.kind(), ShmemErrorKind::NotFound);
+}
+
+#[test]
+fn create_shm_open_failure() {
+    let plan = FaultPlan::new().fail("shm_open", libc::EMFILE);
+
+    let e = ShmemConf::new()
+        .size(4096)
+        .fault_plan(plan.clone())
+        .create()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "shm_open");
+    assert_eq!(e.kind(), ShmemErrorKind::ResourceLimit);
+    assert!(plan.is_done());
+}
+
+#[test]
+fn create_ftruncate_failure() {
+    let os_id = format!("/shmem_fault_ftruncate_{}", std::process::id());
+    let plan = FaultPlan::new().fail("ftruncate", libc::ENOSPC);
+
+    let e = ShmemConf::new()
+        .size(4096)
+        .os_id(&os_id)
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "ftruncate");
+    assert_eq!(e.kind(), ShmemErrorKind::NoSpace);
+
+    // The object must not leak
+    let e = ShmemConf::new().os_id(&os_id).open().err().unwrap();
+    assert_eq!(e.kind(), ShmemErrorKind::NotFound);
+}
+
+#[test]
+fn create_mmap_failure() {
+    let os_id = format!("/shmem_fault_mmap_{}", std::process::id());
+    let plan = FaultPlan::new().fail("mmap", libc::ENOMEM);
+
+    let e = ShmemConf::new()
+        .size(4096)
+        .os_id(&os_id)
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "mmap");
+    assert_eq!(e.kind(), ShmemErrorKind::ResourceLimit);
+
+    let e = ShmemConf::new().os_id(&os_id).open().err().unwrap();
+    assert_eq!(e.kind(), ShmemErrorKind::NotFound);
+
+    // The same goes for backing files
+    let path = std::env::temp_dir().join(format!("shmem_fault_mmap_{}", std::process::id()));
+    let plan = FaultPlan::new().fail("mmap", libc::ENOMEM);
+    let e = ShmemConf::new()
+        .size(4096)
+        .file_backed(&path)
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "mmap");
+    assert!(!path.exists());
+}
+
+#[test]
+fn create_sysv_failure() {
+    let key = 0x5346_0000 | (std::process::id() as i32 & 0xFFFF);
+    let plan = FaultPlan::new().fail("shmat", libc::ENOMEM);
+
+    let e = ShmemConf::new()
+        .size(4096)
+        .sysv(key)
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "shmat");
+
+    // The segment was removed
+    let e = ShmemConf::new().sysv(key).open().err().unwrap();
+    assert_eq!(e.kind(), ShmemErrorKind::NotFound);
+}
+
+#[test]
+fn mlock_failure() {
+    let os_id = format!("/shmem_fault_mlock_{}", std::process::id());
+    let plan = FaultPlan::new().fail("mlock", libc::ENOMEM);
+
+    let e = ShmemConf::new()
+        .size(4096)
+        .os_id(&os_id)
+        .lock_in_memory()
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ShmemErrorKind::ResourceLimit);
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    assert_eq!(
+        unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) },
+        0
+    );
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        assert_eq!(failed_syscall(&e), "mlock");
+    } else {
+        assert!(matches!(
+            e,
+            ShmemError::MemoryLockLimit { required: 4096, .. }
+        ));
+    }
+
+    let e = ShmemConf::new().os_id(&os_id).open().err().unwrap();
+    assert_eq!(e.kind(), ShmemErrorKind::NotFound);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn preallocate_out_of_space() {
+    let os_id = format!("/shmem_fault_fallocate_{}", std::process::id());
+    let plan = FaultPlan::new().fail("posix_fallocate", libc::ENOSPC);
+
+    let e = ShmemConf::new()
+        .size(4096)
+        .os_id(&os_id)
+        .preallocate()
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert!(matches!(
+        e,
+        ShmemError::InsufficientSpace { required: 4096, .. }
+    ));
+    assert_eq!(e.kind(), ShmemErrorKind::NoSpace);
+
+    let e = ShmemConf::new().os_id(&os_id).open().err().unwrap();
+    assert_eq!(e.kind(), ShmemErrorKind::NotFound);
+}
+
+#[test]
+fn open_failures() {
+    let s = ShmemConf::new().size(4096).create().unwrap();
+
+    let plan = FaultPlan::new().fail("open", libc::EACCES);
+    let e = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .fault_plan(plan)
+        .open()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "open");
+    assert_eq!(e.kind(), ShmemErrorKind::PermissionDenied);
+
+    let plan = FaultPlan::new().fail("shm_open", libc::EACCES);
+    let e = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .fault_plan(plan)
+        .open()
+        .err()
+        .unwrap();
+    assert_eq!(failed_syscall(&e), "shm_open");
+    assert_eq!(e.kind(), ShmemErrorKind::PermissionDenied);
+
+    // The first open succeeds, the second one fails
+    let plan = FaultPlan::new().fail_after("open", 1, libc::EMFILE);
+    let conf = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .fault_plan(plan.clone());
+    let _s2 = conf.clone().open().unwrap();
+    assert!(!plan.is_done());
+    let e = conf.open().err().unwrap();
+    assert_eq!(failed_syscall(&e), "open");
+    assert!(plan.is_done());
+}
+
+#[test]
+fn resize_failure() {
+    let plan = FaultPlan::new().fail("resize", libc::ENOSPC);
+    let mut s = ShmemConf::new()
+        .size(4096)
+        .fault_plan(plan)
+        .create()
+        .unwrap();
+
+    let e = s.resize(8192).unwrap_err();
+    assert_eq!(failed_syscall(&e), "resize");
+    assert_eq!(e.kind(), ShmemErrorKind::NoSpace);
+
+    // The mapping is left untouched
+    assert_eq!(s.len(), 4096);
+    unsafe { s.as_slice_mut().unwrap()[4095] = 1 };
+    s.resize(8192).unwrap();
+    assert_eq!(s.len(), 8192);
+}
+
+#[test]
+fn flink_failures() {
+    let dir = std::env::temp_dir();
+    let flink = dir.join(format!("shmem_fault_flink_{}", std::process::id()));
+    let heap_conf = || ShmemConf::new().backend(HeapBackend).size(4096);
+
+    let plan = FaultPlan::new().fail("flink_create", libc::EACCES);
+    let e = heap_conf()
+        .flink(&flink)
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::LinkCreateFailed(_)));
+    assert!(!flink.exists());
+
+    // Neither the flink nor the mapping it points to must leak
+    let plan = FaultPlan::new().fail("flink_write", libc::EIO);
+    let e = heap_conf()
+        .os_id("fault_flink_write")
+        .flink(&flink)
+        .fault_plan(plan)
+        .create()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::LinkWriteFailed(_)));
+    assert!(!flink.exists());
+    assert!(heap_conf().os_id("fault_flink_write").open().is_err());
+
+    let _s = heap_conf().flink(&flink).create().unwrap();
+    let plan = FaultPlan::new().fail("flink_read", libc::EIO);
+    let e = heap_conf()
+        .flink(&flink)
+        .fault_plan(plan)
+        .open()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::LinkReadFailed(_)));
+    let plan = FaultPlan::new().fail("flink_open", libc::EACCES);
+    let e = heap_conf()
+        .flink(&flink)
+        .fault_plan(plan)
+        .open()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::LinkOpenFailed(_)));
+    assert_eq!(e.kind(), ShmemErrorKind::PermissionDenied);
+}
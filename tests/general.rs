@@ -1,6 +1,10 @@
 use std::path::Path;
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use shared_memory::ShmemConf;
+use shared_memory::{ShmemBackend, ShmemConf, ShmemError, ShmemErrorKind, ShmemMapping};
 
 #[test]
 fn create_new() {
@@ -12,7 +16,7 @@ fn create_new() {
     assert!(!s.as_ptr().is_null());
     unsafe {
         assert_eq!(s.as_slice().len(), s.len());
-        assert_eq!(s.as_slice_mut().len(), s.len());
+        assert_eq!(s.as_slice_mut().unwrap().len(), s.len());
     }
 }
 
@@ -29,7 +33,7 @@ fn create_with_flink() {
     assert!(!s.as_ptr().is_null());
     unsafe {
         assert_eq!(s.as_slice().len(), s.len());
-        assert_eq!(s.as_slice_mut().len(), s.len());
+        assert_eq!(s.as_slice_mut().unwrap().len(), s.len());
     }
 
     drop(s);
@@ -51,7 +55,7 @@ fn open_os_id() {
     assert!(!s2.as_ptr().is_null());
     unsafe {
         assert_eq!(s2.as_slice().len(), s2.len());
-        assert_eq!(s2.as_slice_mut().len(), s2.len());
+        assert_eq!(s2.as_slice_mut().unwrap().len(), s2.len());
     }
 
     // Drop the owner of the mapping
@@ -78,7 +82,7 @@ fn open_flink() {
     assert!(!s2.as_ptr().is_null());
     unsafe {
         assert_eq!(s2.as_slice().len(), s2.len());
-        assert_eq!(s2.as_slice_mut().len(), s2.len());
+        assert_eq!(s2.as_slice_mut().unwrap().len(), s2.len());
     }
 
     // Drop the owner of the mapping
@@ -115,3 +119,328 @@ fn share_data() {
         assert_eq!(read_val, shared_val);
     }
 }
+
+#[test]
+fn open_read_only() {
+    let s1 = ShmemConf::new().size(4090).create().unwrap();
+    assert!(!s1.is_read_only());
+
+    // Open with read-only access
+    let os_id = s1.get_os_id().to_string();
+    let mut s2 = ShmemConf::new().os_id(os_id).read_only().open().unwrap();
+
+    assert!(!s2.is_owner());
+    assert!(s2.is_read_only());
+    assert!(s2.len() >= 4090);
+
+    // Writes from the owner are visible through the read-only mapping
+    unsafe {
+        s1.as_ptr().write_volatile(0xAB);
+        assert_eq!(s2.as_slice()[0], 0xAB);
+    }
+
+    // There is no mutable view of a read-only mapping
+    assert!(unsafe { s2.as_slice_mut() }.is_none());
+}
+
+#[test]
+fn open_or_create_flink() {
+    let flink = Path::new("open_or_create1");
+
+    let (s1, created) = ShmemConf::new()
+        .flink(flink)
+        .size(4090)
+        .open_or_create()
+        .unwrap();
+    assert!(created);
+    assert!(s1.is_owner());
+
+    let (s2, created) = ShmemConf::new()
+        .flink(flink)
+        .size(4090)
+        .open_or_create()
+        .unwrap();
+    assert!(!created);
+    assert!(!s2.is_owner());
+    assert_eq!(s1.get_os_id(), s2.get_os_id());
+
+    drop(s2);
+    drop(s1);
+    assert!(!flink.is_file());
+}
+
+#[test]
+fn open_or_create_race() {
+    let os_id = format!("/shmem_race_{:X}", std::process::id());
+
+    // Only one of the threads should end up creating the mapping
+    let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let os_id = os_id.clone();
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                let (s, created) = ShmemConf::new()
+                    .os_id(os_id)
+                    .size(4096)
+                    .open_or_create()
+                    .unwrap();
+                assert_eq!(s.is_owner(), created);
+                // Keep the mapping alive until every thread has it
+                barrier.wait();
+                created
+            })
+        })
+        .collect();
+    let num_created = threads
+        .into_iter()
+        .map(|t| t.join().unwrap())
+        .filter(|created| *created)
+        .count();
+
+    assert_eq!(num_created, 1);
+}
+
+#[test]
+fn init_before_open() {
+    let s1 = ShmemConf::new()
+        .size(4096)
+        .init(|shmem| unsafe { shmem.as_slice_mut().unwrap()[0] = 0x42 })
+        .create()
+        .unwrap();
+    assert_eq!(s1.len(), 4096);
+
+    let os_id = s1.get_os_id().to_string();
+    let s2 = ShmemConf::new()
+        .os_id(os_id)
+        .init_timeout(Duration::from_secs(1))
+        .open()
+        .unwrap();
+    assert_eq!(s2.len(), 4096);
+    assert_eq!(unsafe { s2.as_slice()[0] }, 0x42);
+}
+
+#[test]
+fn init_no_resize() {
+    // The header is written to once the init closure returns, the mapping must not move until then
+    let s = ShmemConf::new()
+        .size(4096)
+        .init(|shmem| {
+            assert!(shmem.resize(64 << 20).is_err());
+            assert!(shmem.refresh().is_err());
+        })
+        .create()
+        .unwrap();
+    assert_eq!(s.len(), 4096);
+}
+
+#[test]
+fn init_timeout() {
+    let os_id = format!("/shmem_init_timeout_{:X}", std::process::id());
+    let (tx_started, rx_started) = channel();
+    let (tx_done, rx_done) = channel::<()>();
+    let rx_done = Mutex::new(rx_done);
+
+    let creator = {
+        let os_id = os_id.clone();
+        thread::spawn(move || {
+            ShmemConf::new()
+                .os_id(os_id)
+                .size(4096)
+                .init(move |_| {
+                    tx_started.send(()).unwrap();
+                    // Block initialization until the opener gave up
+                    rx_done.lock().unwrap().recv().unwrap();
+                })
+                .create()
+                .unwrap();
+        })
+    };
+
+    rx_started.recv().unwrap();
+    let res = ShmemConf::new()
+        .os_id(&os_id)
+        .init_timeout(Duration::from_millis(50))
+        .open();
+    assert!(matches!(res, Err(ShmemError::InitTimeout)));
+
+    tx_done.send(()).unwrap();
+    creator.join().unwrap();
+}
+
+#[test]
+fn init_abandoned() {
+    let os_id = format!("/shmem_init_abandoned_{:X}", std::process::id());
+    let (tx_started, rx_started) = channel();
+    let tx_started = Mutex::new(tx_started);
+
+    let creator = {
+        let os_id = os_id.clone();
+        thread::spawn(move || {
+            ShmemConf::new()
+                .os_id(os_id)
+                .size(4096)
+                .init(move |_| {
+                    tx_started.lock().unwrap().send(()).unwrap();
+                    // Give the opener some time to start waiting on the mapping
+                    thread::sleep(Duration::from_millis(200));
+                    panic!("Creator failed to initialize the mapping");
+                })
+                .create()
+                .unwrap();
+        })
+    };
+
+    rx_started.recv().unwrap();
+    let res = ShmemConf::new()
+        .os_id(&os_id)
+        .init_timeout(Duration::from_secs(5))
+        .open();
+    assert!(matches!(res, Err(ShmemError::InitAbandoned)));
+
+    assert!(creator.join().is_err());
+}
+
+#[test]
+fn init_read_only() {
+    let os_id = format!("/shmem_init_read_only_{:X}", std::process::id());
+    let (tx_started, rx_started) = channel();
+    let tx_started = Mutex::new(tx_started);
+    let (tx_done, rx_done) = channel();
+
+    let creator = {
+        let os_id = os_id.clone();
+        thread::spawn(move || {
+            let s = ShmemConf::new()
+                .os_id(os_id)
+                .size(4096)
+                .init(move |s| {
+                    tx_started.lock().unwrap().send(()).unwrap();
+                    // Give the opener some time to start waiting on the mapping
+                    thread::sleep(Duration::from_millis(500));
+                    unsafe { s.as_ptr().write_volatile(0xAB) };
+                })
+                .create()
+                .unwrap();
+            // Keep the mapping alive until the opener is done with it
+            rx_done.recv().unwrap();
+            drop(s);
+        })
+    };
+
+    // A read-only opener waits for the init closure without touching the header
+    rx_started.recv().unwrap();
+    let s = ShmemConf::new()
+        .os_id(&os_id)
+        .managed_header()
+        .read_only()
+        .init_timeout(Duration::from_secs(5))
+        .open()
+        .unwrap();
+    assert_eq!(unsafe { s.as_slice()[0] }, 0xAB);
+
+    drop(s);
+    tx_done.send(()).unwrap();
+    creator.join().unwrap();
+}
+
+#[test]
+fn header_checks() {
+    let s1 = ShmemConf::new().size(100).schema(1).create().unwrap();
+    let os_id = s1.get_os_id().to_string();
+
+    // Only the user data is exposed
+    let s2 = ShmemConf::new().os_id(&os_id).schema(1).open().unwrap();
+    assert_eq!(s2.len(), 100);
+    assert_eq!(s1.as_ptr() as usize % 8, 0);
+
+    let res = ShmemConf::new().os_id(&os_id).schema(2).open();
+    assert!(matches!(
+        res,
+        Err(ShmemError::SchemaMismatch {
+            expected: 2,
+            found: 1
+        })
+    ));
+
+    let res = ShmemConf::new().os_id(&os_id).schema(1).size(200).open();
+    assert!(matches!(res, Err(ShmemError::MapTooSmall { .. })));
+}
+
+#[test]
+fn header_invalid() {
+    // Mapping that was not created with a header
+    let mut s1 = ShmemConf::new().size(4096).create().unwrap();
+    unsafe { s1.as_slice_mut().unwrap()[..64].fill(0xFF) };
+
+    let os_id = s1.get_os_id().to_string();
+    let res = ShmemConf::new().os_id(os_id).managed_header().open();
+    assert!(matches!(res, Err(ShmemError::HeaderInvalid)));
+}
+
+#[test]
+fn open_error_context() {
+    let os_id = format!("/shmem_missing_{:X}", std::process::id());
+    let err = ShmemConf::new().os_id(&os_id).open().err().unwrap();
+
+    assert_eq!(err.kind(), ShmemErrorKind::NotFound);
+    assert!(std::error::Error::source(&err).is_some());
+    assert!(err.to_string().contains(&os_id));
+}
+
+/// Backend that hands out plain heap buffers and records what it removes
+#[derive(Default)]
+struct VecBackend {
+    unlinked: Arc<Mutex<Vec<String>>>,
+}
+// u64 elements give the buffer the alignment required by ShmemMapping
+struct VecMapping(String, Vec<u64>, *mut u8);
+unsafe impl ShmemMapping for VecMapping {
+    fn os_id(&self) -> &str {
+        &self.0
+    }
+    fn len(&self) -> usize {
+        self.1.len() * 8
+    }
+    fn as_ptr(&self) -> *mut u8 {
+        self.2
+    }
+}
+impl ShmemBackend for VecBackend {
+    fn create(&self, os_id: &str, size: usize) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        let mut data = vec![0; size.div_ceil(8)];
+        let ptr = data.as_mut_ptr() as *mut u8;
+        Ok(Box::new(VecMapping(os_id.to_string(), data, ptr)))
+    }
+    fn open(&self, _: &str, _: usize, _: bool) -> Result<Box<dyn ShmemMapping>, ShmemError> {
+        Err(ShmemError::Unsupported("Opening"))
+    }
+    fn unlink(&self, mapping: &dyn ShmemMapping) -> Result<(), ShmemError> {
+        self.unlinked
+            .lock()
+            .unwrap()
+            .push(mapping.os_id().to_string());
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_backend() {
+    let backend = VecBackend::default();
+    let unlinked = backend.unlinked.clone();
+    let conf = ShmemConf::new()
+        .size(4096)
+        .managed_header()
+        .backend(backend);
+
+    let s = conf.clone().os_id("first").create().unwrap();
+    assert_eq!(s.get_os_id(), "first");
+    assert_eq!(s.len(), 4096);
+    assert!(conf.clone().os_id("first").open().is_err());
+    drop(s);
+
+    let mut s = conf.os_id("second").create().unwrap();
+    s.set_owner(false);
+    drop(s);
+    assert_eq!(*unlinked.lock().unwrap(), vec!["first".to_string()]);
+}
@@ -1,4 +1,4 @@
-use shared_memory::ShmemConf;
+use shared_memory::{HeapBackend, ShmemConf};
 use std::sync::mpsc::channel;
 use std::thread;
 
@@ -88,3 +88,52 @@ fn posix_behavior() {
     thread_b.join().unwrap();
     thread_c.join().unwrap();
 }
+
+#[test]
+fn heap_persistence() {
+    let os_id = {
+        let mut shmem = ShmemConf::new()
+            .size(4096)
+            .backend(HeapBackend)
+            .create()
+            .unwrap();
+        shmem.set_owner(false);
+        String::from(shmem.get_os_id())
+    };
+    let mut shmem = ShmemConf::new()
+        .os_id(&os_id)
+        .backend(HeapBackend)
+        .open()
+        .unwrap();
+    shmem.set_owner(true);
+    drop(shmem);
+    assert!(ShmemConf::new()
+        .os_id(os_id)
+        .backend(HeapBackend)
+        .open()
+        .is_err());
+}
+
+#[test]
+fn heap_posix_behavior() {
+    let conf = ShmemConf::new().size(4096).backend(HeapBackend);
+    let s1 = conf.clone().create().unwrap();
+    let os_id = String::from(s1.get_os_id());
+    // Creating two `Shmem`s with the same `os_id` should fail
+    assert!(conf.clone().os_id(&os_id).create().is_err());
+
+    let mut s2 = conf.clone().os_id(&os_id).open().unwrap();
+    s2.set_owner(true);
+    let s3 = conf.clone().os_id(&os_id).open().unwrap();
+
+    // The segment cannot be reopened once an owner is dropped, even while other instances are alive
+    drop(s2);
+    assert!(conf.clone().os_id(&os_id).open().is_err());
+    drop(s1);
+
+    // The memory stays valid for the remaining instance
+    unsafe {
+        s3.as_ptr().write_volatile(0xAB);
+        assert_eq!(s3.as_ptr().read_volatile(), 0xAB);
+    }
+}
@@ -0,0 +1,828 @@
+#![cfg(unix)]
+
+use std::os::unix::net::UnixStream;
+
+use shared_memory::ShmemConf;
+
+/// Returns the size of the pages of the system
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn create_with_mode() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    // Group/other write bits would normally be stripped by the default umask
+    let s = ShmemConf::new()
+        .size(4096)
+        .mode(0o666)
+        .gid(unsafe { libc::getegid() })
+        .create()
+        .unwrap();
+
+    let meta = std::fs::metadata(format!("/dev/shm{}", s.get_os_id())).unwrap();
+    assert_eq!(meta.permissions().mode() & 0o777, 0o666);
+    assert_eq!(meta.gid(), unsafe { libc::getegid() });
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn memfd_send_recv() {
+    let (tx, rx) = UnixStream::pair().unwrap();
+
+    let s1 = ShmemConf::new().size(4096).memfd().create().unwrap();
+    assert!(s1.is_owner());
+    assert!(!std::path::Path::new(&format!("/dev/shm/{}", s1.get_os_id())).exists());
+
+    s1.send_fd(&tx).unwrap();
+    let s2 = ShmemConf::new().recv_fd(&rx).unwrap();
+
+    assert!(!s2.is_owner());
+    assert_eq!(s2.get_os_id(), s1.get_os_id());
+    assert_eq!(s2.len(), 4096);
+    unsafe {
+        s1.as_ptr().write_volatile(0xAB);
+        assert_eq!(s2.as_ptr().read_volatile(), 0xAB);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn memfd_seal() {
+    let (tx, rx) = UnixStream::pair().unwrap();
+
+    let mut s1 = ShmemConf::new().size(4096).memfd().create().unwrap();
+    unsafe { s1.as_slice_mut().unwrap()[..5].copy_from_slice(b"hello") };
+    assert!(s1.as_sealed_slice().is_none());
+
+    // Cannot seal while a peer still has a writable mapping
+    s1.send_fd(&tx).unwrap();
+    let s2 = ShmemConf::new().recv_fd(&rx).unwrap();
+    assert!(s1.seal().is_err());
+    assert!(!s1.is_read_only());
+    drop(s2);
+
+    s1.seal().unwrap();
+    assert!(s1.is_sealed());
+    assert!(s1.is_read_only());
+    assert_eq!(&s1.as_sealed_slice().unwrap()[..5], b"hello");
+
+    s1.send_fd(&tx).unwrap();
+    let s2 = ShmemConf::new().recv_fd(&rx).unwrap();
+    assert!(s2.is_read_only());
+    assert_eq!(&s2.as_sealed_slice().unwrap()[..5], b"hello");
+
+    // The header still has to be marked as ready once the init closure returns
+    let mut s4 = ShmemConf::new()
+        .size(4096)
+        .memfd()
+        .init(|shmem| assert!(shmem.seal().is_err()))
+        .create()
+        .unwrap();
+    assert!(!s4.is_sealed());
+    s4.seal().unwrap();
+
+    // Sealing a window keeps the contents of the window in place
+    let page = page_size();
+    let s5 = ShmemConf::new().size(2 * page).memfd().create().unwrap();
+    unsafe { s5.as_ptr().add(page).write_bytes(0x42, page) };
+    s5.send_fd(&tx).unwrap();
+    drop(s5);
+    let mut s6 = ShmemConf::new()
+        .offset(page as u64)
+        .size(page)
+        .recv_fd(&rx)
+        .unwrap();
+    s6.seal().unwrap();
+    assert!(s6.as_sealed_slice().unwrap().iter().all(|&b| b == 0x42));
+
+    // Regular shm objects cannot be sealed
+    let mut s3 = ShmemConf::new().size(4096).create().unwrap();
+    assert!(s3.seal().is_err());
+    unsafe { s3.as_slice_mut().unwrap()[0] = 1 };
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn resize_and_refresh() {
+    for header in [false, true] {
+        let mut conf = ShmemConf::new().size(4096);
+        if header {
+            conf = conf.managed_header();
+        }
+        let mut s1 = conf.clone().create().unwrap();
+        let mut s2 = conf.os_id(s1.get_os_id()).open().unwrap();
+        assert!(!s2.needs_refresh());
+
+        s1.resize(1 << 20).unwrap();
+        assert_eq!(s1.len(), 1 << 20);
+        unsafe { s1.as_slice_mut().unwrap()[(1 << 20) - 1] = 0xAB };
+
+        assert!(s2.needs_refresh());
+        assert_eq!(s2.len(), 4096);
+        assert!(s2.refresh().unwrap());
+        assert!(!s2.needs_refresh());
+        assert!(!s2.refresh().unwrap());
+        assert_eq!(s2.len(), 1 << 20);
+        assert_eq!(unsafe { s2.as_slice()[(1 << 20) - 1] }, 0xAB);
+
+        // Peers can shrink it back
+        s2.resize(4096).unwrap();
+        assert!(s1.needs_refresh());
+        s1.refresh().unwrap();
+        assert_eq!(s1.len(), 4096);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn huge_pages() {
+    use shared_memory::{HugePageSize, ShmemError};
+
+    let conf = ShmemConf::new()
+        .size(4096)
+        .managed_header()
+        .huge_pages(HugePageSize::Size2MiB);
+    match conf.clone().create() {
+        // The size is rounded up to the page size
+        Ok(mut s) => {
+            assert_eq!(s.len(), (2 << 20) - 128);
+            s.resize((2 << 20) + 1).unwrap();
+            assert_eq!(s.len(), (4 << 20) - 128);
+        }
+        Err(ShmemError::HugePagesUnavailable(_)) => {
+            // Regular pages are used instead when asked to
+            let s = conf.huge_pages_fallback().create().unwrap();
+            assert_eq!(s.len(), 4096);
+        }
+        Err(e) => panic!("unexpected error {}", e),
+    }
+}
+
+#[test]
+fn file_backed() {
+    use shared_memory::ShmemErrorKind;
+
+    let path = std::env::temp_dir().join(format!("shmem_file_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut s1 = ShmemConf::new()
+        .size(8192)
+        .file_backed(&path)
+        .create()
+        .unwrap();
+    assert_eq!(s1.get_os_id(), path.to_str().unwrap());
+    unsafe { s1.as_slice_mut().unwrap()[4096..4101].copy_from_slice(b"hello") };
+    s1.flush_range(4096..4101).unwrap();
+    s1.flush().unwrap();
+    assert_eq!(
+        s1.flush_range(0..8193).unwrap_err().kind(),
+        ShmemErrorKind::InvalidInput
+    );
+
+    // Keep the file around once we are done with it
+    s1.set_owner(false);
+    drop(s1);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 8192);
+    assert_eq!(&std::fs::read(&path).unwrap()[4096..4101], b"hello");
+
+    let mut s2 = ShmemConf::new().file_backed(&path).open().unwrap();
+    assert_eq!(s2.len(), 8192);
+    assert_eq!(unsafe { &s2.as_slice()[4096..4101] }, b"hello");
+
+    s2.set_owner(true);
+    drop(s2);
+    assert!(!path.exists());
+}
+
+#[test]
+fn sysv_segment() {
+    use shared_memory::ShmemError;
+
+    let key = 0x5348_0000 | (std::process::id() as i32 & 0xFFFF);
+    let s1 = ShmemConf::new().size(4096).sysv(key).create().unwrap();
+    assert!(matches!(
+        ShmemConf::new().size(4096).sysv(key).create(),
+        Err(ShmemError::MappingIdExists)
+    ));
+
+    // The size is discovered from the segment itself
+    let s2 = ShmemConf::new().sysv(key).read_only().open().unwrap();
+    assert_eq!(s2.get_os_id(), s1.get_os_id());
+    assert_eq!(s2.len(), 4096);
+    unsafe {
+        s1.as_ptr().write_volatile(0xAB);
+        assert_eq!(s2.as_ptr().read_volatile(), 0xAB);
+    }
+
+    drop(s2);
+    drop(s1);
+    assert!(ShmemConf::new().sysv(key).open().is_err());
+
+    // Removing the segment behind the back of its owner lets another one reuse the key
+    let s1 = ShmemConf::new().size(4096).sysv(key).create().unwrap();
+    unsafe {
+        libc::shmctl(
+            libc::shmget(key, 0, 0),
+            libc::IPC_RMID,
+            std::ptr::null_mut(),
+        )
+    };
+    let s3 = ShmemConf::new().size(4096).sysv(key).create().unwrap();
+    drop(s1);
+    assert!(ShmemConf::new().sysv(key).open().is_ok());
+    drop(s3);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn lock_and_populate() {
+    use shared_memory::ShmemError;
+
+    fn locked_kb() -> u64 {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|l| l.starts_with("VmLck:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    let s1 = ShmemConf::new()
+        .size(64 << 10)
+        .lock_in_memory()
+        .populate()
+        .create()
+        .unwrap();
+    assert!(locked_kb() >= 64);
+
+    let s2 = ShmemConf::new()
+        .os_id(s1.get_os_id())
+        .populate()
+        .open()
+        .unwrap();
+    unsafe {
+        *s1.as_ptr() = 0x42;
+        assert_eq!(*s2.as_ptr(), 0x42);
+    }
+
+    // Locking more than RLIMIT_MEMLOCK allows is reported as such, root is not bound by the limit
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    assert_eq!(
+        unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) },
+        0
+    );
+    if limit.rlim_cur != libc::RLIM_INFINITY && unsafe { libc::geteuid() } != 0 {
+        let size = limit.rlim_cur as usize + (1 << 20);
+        let e = ShmemConf::new()
+            .size(size)
+            .lock_in_memory()
+            .create()
+            .err()
+            .unwrap();
+        assert!(matches!(e, ShmemError::MemoryLockLimit { .. }));
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn advise() {
+    use shared_memory::{Advice, ShmemError};
+
+    let page = page_size();
+    let s = ShmemConf::new().size(4 * page).create().unwrap();
+    let data = unsafe { std::slice::from_raw_parts_mut(s.as_ptr(), s.len()) };
+    data.fill(0xAA);
+
+    s.advise(0..s.len(), Advice::WillNeed).unwrap();
+    s.advise(1..2, Advice::Sequential).unwrap();
+
+    // Only the pages fully inside the range get their contents discarded
+    s.advise(page / 2..3 * page + 1, Advice::Remove).unwrap();
+    assert_eq!(data[page - 1], 0xAA);
+    assert!(data[page..3 * page].iter().all(|b| *b == 0));
+    assert_eq!(data[3 * page], 0xAA);
+
+    // Shared pages dropped from our address space read back from the object
+    s.advise(0..4 * page, Advice::DontNeed).unwrap();
+    assert_eq!(data[0], 0xAA);
+    assert_eq!(data[page], 0);
+
+    assert!(matches!(
+        s.advise(0..4 * page + 1, Advice::WillNeed),
+        Err(ShmemError::RangeOutOfBounds { .. })
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn fixed_and_same_address() {
+    use shared_memory::{ShmemError, ShmemErrorKind};
+
+    // Find a free range by mapping and unmapping it
+    let size = 1 << 20;
+    let free_addr = unsafe {
+        let ptr = libc::mmap(
+            std::ptr::null_mut(),
+            size,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(ptr, libc::MAP_FAILED);
+        libc::munmap(ptr, size);
+        ptr as usize
+    };
+
+    let s1 = ShmemConf::new()
+        .size(4096)
+        .fixed_address(free_addr)
+        .create()
+        .unwrap();
+    assert_eq!(s1.as_ptr() as usize, free_addr);
+
+    // Nothing gets replaced
+    let e = ShmemConf::new()
+        .os_id(s1.get_os_id())
+        .fixed_address(free_addr)
+        .open()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::AddressUnavailable { addr } if addr == free_addr));
+    assert_eq!(e.kind(), ShmemErrorKind::AlreadyExists);
+    drop(s1);
+
+    // The opener maps the mapping where the creator had it
+    let (os_id, base) = {
+        let mut s = ShmemConf::new().size(4096).same_address().create().unwrap();
+        s.set_owner(false);
+        let base = s.as_ptr() as usize;
+        unsafe { *(s.as_ptr() as *mut usize) = base };
+        (s.get_os_id().to_string(), base)
+    };
+    let mut s = ShmemConf::new()
+        .os_id(&os_id)
+        .same_address()
+        .open()
+        .unwrap();
+    s.set_owner(true);
+    assert_eq!(s.as_ptr() as usize, base);
+    assert_eq!(unsafe { *(s.as_ptr() as *const usize) }, base);
+
+    // The address is taken by our own mapping
+    let e = ShmemConf::new()
+        .os_id(&os_id)
+        .same_address()
+        .open()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::AddressUnavailable { .. }));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn partial_view() {
+    use shared_memory::{ShmemError, ShmemErrorKind};
+
+    let page = page_size();
+    let s = ShmemConf::new().size(4 * page).create().unwrap();
+    unsafe { *s.as_ptr().add(2 * page) = 0x42 };
+
+    let mut view = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .offset(2 * page as u64)
+        .size(page)
+        .open()
+        .unwrap();
+    assert_eq!(view.len(), page);
+    assert_eq!(unsafe { *view.as_ptr() }, 0x42);
+    assert!(!view.needs_refresh());
+    assert_eq!(
+        view.resize(2 * page).unwrap_err().kind(),
+        ShmemErrorKind::Unsupported
+    );
+
+    // Without a size, the view spans up to the end of the object
+    let view = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .offset(2 * page as u64)
+        .open()
+        .unwrap();
+    assert_eq!(view.len(), 2 * page);
+
+    let e = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .offset(3 * page as u64)
+        .size(2 * page)
+        .open()
+        .err()
+        .unwrap();
+    assert!(
+        matches!(e, ShmemError::MapTooSmall { required, actual } if required == 5 * page && actual == 4 * page)
+    );
+
+    let e = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .offset(1)
+        .open()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::UnalignedOffset { offset: 1, .. }));
+
+    // An explicit offset of 0 maps a window too
+    let view = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .offset(0)
+        .size(page)
+        .open()
+        .unwrap();
+    assert_eq!(view.len(), page);
+    assert!(!view.needs_refresh());
+
+    // The whole object must cover the requested size
+    let e = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .size(8 * page)
+        .open()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::MapTooSmall { .. }));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn guard_pages() {
+    let page = page_size();
+    // Returns whether `addr` is in an inaccessible region of our address space
+    let is_guard = |addr: usize| {
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        maps.lines().any(|l| {
+            let mut fields = l.split_whitespace();
+            let (start, end) = fields.next().unwrap().split_once('-').unwrap();
+            let start = usize::from_str_radix(start, 16).unwrap();
+            let end = usize::from_str_radix(end, 16).unwrap();
+            fields.next() == Some("---p") && (start..end).contains(&addr)
+        })
+    };
+    let has_guards = |s: &shared_memory::Shmem| {
+        is_guard(s.as_ptr() as usize - page) && is_guard(s.as_ptr() as usize + s.len())
+    };
+
+    let mut s = ShmemConf::new()
+        .size(2 * page)
+        .guard_pages()
+        .create()
+        .unwrap();
+    assert!(has_guards(&s));
+    unsafe { std::ptr::write_bytes(s.as_ptr(), 0x42, s.len()) };
+
+    let view = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .guard_pages()
+        .open()
+        .unwrap();
+    assert!(has_guards(&view));
+    assert_eq!(unsafe { *view.as_ptr().add(2 * page - 1) }, 0x42);
+
+    // The guard pages follow the mapping when it grows
+    s.resize(8 * page).unwrap();
+    assert!(has_guards(&s));
+    assert_eq!(unsafe { *s.as_ptr().add(page) }, 0x42);
+
+    assert!(ShmemConf::new()
+        .size(page)
+        .sysv(0x5348_4d47)
+        .guard_pages()
+        .create()
+        .is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn numa_placement() {
+    let page = page_size();
+
+    let mut s = ShmemConf::new()
+        .size(4 * page)
+        .numa_node(0)
+        .populate()
+        .create()
+        .unwrap();
+    assert_eq!(s.numa_nodes().unwrap(), vec![4]);
+
+    // The policy also covers the pages added by a resize
+    s.resize(8 * page).unwrap();
+    unsafe { std::ptr::write_bytes(s.as_ptr(), 0, s.len()) };
+    assert_eq!(s.numa_nodes().unwrap(), vec![8]);
+
+    let s = ShmemConf::new()
+        .size(4 * page)
+        .numa_interleave()
+        .create()
+        .unwrap();
+    assert!(s.numa_nodes().unwrap().is_empty());
+    unsafe { std::ptr::write_bytes(s.as_ptr(), 0, s.len()) };
+    assert_eq!(s.numa_nodes().unwrap().iter().sum::<usize>(), 4);
+
+    // Preallocated storage follows the policy, pages show up once they are mapped in
+    let mut s = ShmemConf::new()
+        .size(4 * page)
+        .numa_node(0)
+        .preallocate()
+        .create()
+        .unwrap();
+    s.resize(8 * page).unwrap();
+    for offset in (0..s.len()).step_by(page) {
+        unsafe { s.as_ptr().add(offset).read_volatile() };
+    }
+    assert_eq!(s.numa_nodes().unwrap(), vec![8]);
+
+    assert!(ShmemConf::new()
+        .size(page)
+        .numa_node(u32::MAX)
+        .create()
+        .is_err());
+}
+
+#[test]
+fn private_view() {
+    use shared_memory::ShmemErrorKind;
+
+    let page = page_size();
+    let s = ShmemConf::new().size(2 * page).create().unwrap();
+    unsafe { std::ptr::write_bytes(s.as_ptr(), 1, s.len()) };
+
+    let mut view = s.private_view().unwrap();
+    assert!(!view.is_owner());
+    assert_eq!(view.len(), s.len());
+    unsafe { *view.as_ptr() = 2 };
+    assert_eq!(unsafe { *s.as_ptr() }, 1);
+
+    // Pages the view did not write to are still shared
+    unsafe { *s.as_ptr().add(page) = 3 };
+    assert_eq!(unsafe { *view.as_ptr().add(page) }, 3);
+    unsafe { *s.as_ptr() = 4 };
+    assert_eq!(unsafe { *view.as_ptr() }, 2);
+
+    assert_eq!(
+        view.resize(4 * page).unwrap_err().kind(),
+        ShmemErrorKind::Unsupported
+    );
+    drop(view);
+
+    // Read-only mappings get writable views
+    let ro = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .read_only()
+        .open()
+        .unwrap();
+    let view = ro.private_view().unwrap();
+    assert!(!view.is_read_only());
+    unsafe { *view.as_ptr() = 5 };
+    assert_eq!(unsafe { *s.as_ptr() }, 4);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn preallocate() {
+    use shared_memory::{ShmemError, ShmemErrorKind};
+
+    let page = page_size();
+    let free = ShmemConf::new().free_space().unwrap();
+    assert!(free > 0);
+
+    let mut s = ShmemConf::new().size(page).preallocate().create().unwrap();
+    s.resize(4 * page).unwrap();
+
+    // Larger than the whole filesystem
+    let e = ShmemConf::new()
+        .size(free as usize + (1 << 40))
+        .preallocate()
+        .create()
+        .err()
+        .unwrap();
+    assert!(matches!(e, ShmemError::InsufficientSpace { .. }));
+
+    assert_eq!(
+        ShmemConf::new().memfd().free_space().unwrap_err().kind(),
+        ShmemErrorKind::Unsupported
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn checked_access() {
+    use shared_memory::ShmemError;
+
+    let page = page_size();
+    let mut s = ShmemConf::new().size(4 * page).create().unwrap();
+    let mut peer = ShmemConf::new().os_id(s.get_os_id()).open().unwrap();
+
+    peer.try_write_from(page - 2, &[1, 2, 3, 4]).unwrap();
+    let mut buf = [0u8; 4];
+    s.try_read_into(page - 2, &mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3, 4]);
+
+    // The peer keeps mapping 4 pages but only the first one is still backed
+    s.resize(page).unwrap();
+    let e = peer.try_read_into(page - 2, &mut buf).err().unwrap();
+    assert!(matches!(e, ShmemError::PageFault { offset } if offset == page));
+    assert_eq!(buf[..2], [1, 2]);
+    let e = peer.try_write_from(3 * page, &buf).err().unwrap();
+    assert!(matches!(e, ShmemError::PageFault { offset } if offset == 3 * page));
+
+    assert!(matches!(
+        peer.try_read_into(4 * page, &mut buf).err().unwrap(),
+        ShmemError::RangeOutOfBounds { .. }
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn checked_access_sandboxed() {
+    use shared_memory::ShmemError;
+
+    let page = page_size();
+
+    // Seccomp filters only apply to the thread that installs them
+    std::thread::spawn(move || {
+        // Fail process_vm_readv() with EPERM and allow every other syscall
+        let filter = [
+            libc::sock_filter {
+                code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16,
+                jt: 0,
+                jf: 0,
+                k: 0,
+            },
+            libc::sock_filter {
+                code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+                jt: 0,
+                jf: 1,
+                k: libc::SYS_process_vm_readv as u32,
+            },
+            libc::sock_filter {
+                code: (libc::BPF_RET | libc::BPF_K) as u16,
+                jt: 0,
+                jf: 0,
+                k: libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            },
+            libc::sock_filter {
+                code: (libc::BPF_RET | libc::BPF_K) as u16,
+                jt: 0,
+                jf: 0,
+                k: libc::SECCOMP_RET_ALLOW,
+            },
+        ];
+        let prog = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr() as *mut _,
+        };
+        unsafe {
+            assert_eq!(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0), 0);
+            assert_eq!(
+                libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &prog),
+                0
+            );
+        }
+
+        // The peer keeps mapping 4 pages but only the first one is still backed
+        let mut s = ShmemConf::new().size(4 * page).create().unwrap();
+        let mut peer = ShmemConf::new().os_id(s.get_os_id()).open().unwrap();
+        s.resize(page).unwrap();
+        let e = peer.try_write_from(page - 2, &[1, 2, 3, 4]).err().unwrap();
+        assert!(matches!(e, ShmemError::PageFault { offset } if offset == page));
+        let mut buf = [0u8; 4];
+        let e = peer.try_read_into(page - 2, &mut buf).err().unwrap();
+        assert!(matches!(e, ShmemError::PageFault { offset } if offset == page));
+        assert_eq!(buf[..2], [1, 2]);
+        let e = peer.try_write_from(3 * page, &buf).err().unwrap();
+        assert!(matches!(e, ShmemError::PageFault { offset } if offset == 3 * page));
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn protect() {
+    use shared_memory::{HeapBackend, Protection, ShmemErrorKind};
+
+    let page = page_size();
+    let mut s = ShmemConf::new().size(4 * page).create().unwrap();
+    unsafe { *s.as_ptr().add(page) = 1 };
+
+    unsafe { s.protect(page..2 * page, Protection::Read).unwrap() };
+    assert_eq!(unsafe { *s.as_ptr().add(page) }, 1);
+    // Only the pages touched by the range are protected
+    unsafe { *s.as_ptr().add(page - 1) = 2 };
+    unsafe { *s.as_ptr().add(2 * page) = 2 };
+    unsafe { s.protect(page..2 * page, Protection::ReadWrite).unwrap() };
+    unsafe { *s.as_ptr().add(page) = 3 };
+
+    // Split protections must not prevent resizing
+    unsafe { s.protect(0..page, Protection::None).unwrap() };
+    s.resize(8 * page).unwrap();
+    assert_eq!(unsafe { *s.as_ptr().add(page) }, 3);
+
+    let ro = ShmemConf::new()
+        .os_id(s.get_os_id())
+        .read_only()
+        .open()
+        .unwrap();
+    assert_eq!(
+        unsafe { ro.protect(0..page, Protection::ReadWrite) }
+            .unwrap_err()
+            .kind(),
+        ShmemErrorKind::Unsupported
+    );
+    unsafe { ro.protect(0..page, Protection::None).unwrap() };
+
+    // The page of the managed header stays writable, the data starts 128 bytes into it
+    let header = 128;
+    let mut s = ShmemConf::new()
+        .size(4 * page)
+        .managed_header()
+        .create()
+        .unwrap();
+    assert_eq!(
+        unsafe { s.protect(0..1, Protection::Read) }
+            .unwrap_err()
+            .kind(),
+        ShmemErrorKind::Unsupported
+    );
+    unsafe {
+        s.protect(page - header..2 * page, Protection::Read)
+            .unwrap()
+    };
+    s.resize(5 * page).unwrap();
+
+    // Nor while the init closure runs
+    ShmemConf::new()
+        .size(2 * page)
+        .init(move |shmem| {
+            let e = unsafe { shmem.protect(page..2 * page, Protection::Read) }.unwrap_err();
+            assert_eq!(e.kind(), ShmemErrorKind::Unsupported);
+        })
+        .create()
+        .unwrap();
+
+    // Heap segments are not made of whole pages
+    let heap = ShmemConf::new()
+        .size(4 * page)
+        .backend(HeapBackend)
+        .create()
+        .unwrap();
+    assert_eq!(
+        unsafe { heap.protect(0..page, Protection::Read) }
+            .unwrap_err()
+            .kind(),
+        ShmemErrorKind::Unsupported
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn init_creator_killed() {
+    use shared_memory::ShmemError;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    // Runs in the child process spawned below
+    if let Ok(os_id) = std::env::var("SHMEM_KILLED_CREATOR") {
+        ShmemConf::new()
+            .os_id(os_id)
+            .size(4096)
+            .init(|_| {
+                println!("initializing");
+                std::thread::sleep(Duration::from_secs(60));
+            })
+            .create()
+            .unwrap();
+        return;
+    }
+
+    let os_id = format!("/shmem_killed_creator_{}", std::process::id());
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "init_creator_killed", "--nocapture"])
+        .env("SHMEM_KILLED_CREATOR", &os_id)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    assert!(stdout.lines().any(|l| l.unwrap().ends_with("initializing")));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // The creator can no longer finish, openers must not wait for the whole timeout
+    let start = Instant::now();
+    let res = ShmemConf::new()
+        .os_id(&os_id)
+        .init_timeout(Duration::from_secs(30))
+        .open();
+    assert!(matches!(res, Err(ShmemError::InitAbandoned)));
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    // Nobody owns the object anymore
+    let c_id = std::ffi::CString::new(os_id).unwrap();
+    assert_eq!(unsafe { libc::shm_unlink(c_id.as_ptr()) }, 0);
+}